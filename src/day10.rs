@@ -1,75 +1,214 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+/// A pair of delimiters and what they score in the syntax checker.
+pub struct Delimiter {
+    pub open: char,
+    pub close: char,
+    /// Score of an unexpected closing character
+    pub error_points: usize,
+    /// Value of the closing character in the autocomplete score
+    pub completion_points: usize,
+}
 
-fn points(t: char) -> usize {
-    match t {
-        ']' => 57,
-        '}' => 1197,
-        ')' => 3,
-        '>' => 25137,
-        _ => 0
-    }
+pub struct Grammar {
+    delimiters: Vec<Delimiter>,
 }
 
-fn score_map(t: char) -> usize {
-    match t {
-        ']' => 2,
-        '}' => 3,
-        ')' => 1,
-        '>' => 4,
-        _ => 0
-    }
+/// A single character change, columns start at 1.
+#[derive(Debug, PartialEq)]
+pub enum Fix {
+    Replace { column: usize, with: char },
+    Insert { column: usize, c: char },
+    Delete { column: usize },
 }
 
-fn closing(t: char) -> char {
-        match t {
-            '[' => ']',
-            '{' => '}',
-            '(' => ')',
-            '<' => '>',
-        _ => 'X'
+impl Display for Fix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fix::Replace { column, with } => write!(f, "replace column {} with '{}'", column, with),
+            Fix::Insert { column, c } => write!(f, "insert '{}' at column {}", c, column),
+            Fix::Delete { column } => write!(f, "delete column {}", column),
+        }
     }
 }
 
-fn is_opening(t: char) -> bool {
-    match t {
-        ']' | '}' | ')' | '>' => false,
-        _ => true
-    }
+pub enum LineStatus {
+    Valid,
+    Corrupted {
+        column: usize,
+        /// None when the line closes more chunks than it opened
+        expected: Option<char>,
+        found: char,
+        points: usize,
+    },
+    Incomplete {
+        completion: String,
+        score: usize,
+    },
 }
 
-fn line_check(val: &str) -> [usize; 2] {
-    let mut stack: Vec<char> = vec![];
-    let mut p = 0;
-    let mut score = 0;
+impl Grammar {
+    /// Each character can only appear once in the table, so that a line reads one way.
+    pub fn new(delimiters: Vec<Delimiter>) -> Result<Grammar, String> {
+        let mut used: Vec<char> = vec![];
+
+        for d in &delimiters {
+            if d.open == d.close {
+                return Err(format!("Delimiter '{}' opens and closes", d.open));
+            }
+
+            for c in [d.open, d.close] {
+                if used.contains(&c) {
+                    return Err(format!("Delimiter '{}' is used twice", c));
+                }
+                used.push(c);
+            }
+        }
+
+        Ok(Grammar { delimiters })
+    }
+
+    pub fn standard() -> Grammar {
+        Grammar::new(vec![
+            Delimiter { open: '(', close: ')', error_points: 3, completion_points: 1 },
+            Delimiter { open: '[', close: ']', error_points: 57, completion_points: 2 },
+            Delimiter { open: '{', close: '}', error_points: 1197, completion_points: 3 },
+            Delimiter { open: '<', close: '>', error_points: 25137, completion_points: 4 },
+        ]).expect("Standard delimiters are all different")
+    }
+
+    fn opening(&self, c: char) -> Option<&Delimiter> {
+        self.delimiters.iter().find(|d| d.open == c)
+    }
+
+    fn closing(&self, c: char) -> Option<&Delimiter> {
+        self.delimiters.iter().find(|d| d.close == c)
+    }
+
+    pub fn check(&self, line: &[char]) -> Result<LineStatus, String> {
+        let mut stack: Vec<&Delimiter> = vec![];
+
+        for (i, c) in line.iter().enumerate() {
+            if let Some(d) = self.opening(*c) {
+                stack.push(d);
+                continue;
+            }
+
+            let d = match self.closing(*c) {
+                Some(d) => d,
+                None => return Err(format!("Unknown delimiter '{}' at column {}", c, i + 1)),
+            };
 
-    for c in val.chars() {
-        if is_opening(c) {
-            stack.push(c)
-        } else {
             match stack.pop() {
-                Some(t) => {
-                    if c != closing(t) {
-                        p = points(c);
-                        break;
-                    }
-                },
-                None => println!("Line is too long")
+                Some(t) if t.close == *c => {},
+                t => return Ok(LineStatus::Corrupted {
+                    column: i + 1,
+                    expected: t.map(|t| t.close),
+                    found: *c,
+                    points: d.error_points,
+                }),
             }
         }
+
+        if stack.is_empty() {
+            return Ok(LineStatus::Valid);
+        }
+
+        let mut completion = String::new();
+        let mut score = 0;
+        while let Some(t) = stack.pop() {
+            completion.push(t.close);
+            score = score * 5 + t.completion_points;
+        }
+
+        Ok(LineStatus::Incomplete { completion, score })
+    }
+
+    fn is_corrupted(&self, line: &[char]) -> bool {
+        matches!(self.check(line), Ok(LineStatus::Corrupted { .. }))
+    }
+
+    /// Look for a single character change that leaves the line uncorrupted, trying first the
+    /// obvious ones at the corrupted column, then every edit up to that column.
+    pub fn repair(&self, line: &[char]) -> Option<Fix> {
+        let (column, expected) = match self.check(line) {
+            Ok(LineStatus::Corrupted { column, expected, .. }) => (column, expected),
+            _ => return None,
+        };
+
+        let apply = |fix: &Fix| -> Vec<char> {
+            let mut fixed = line.to_vec();
+            match fix {
+                Fix::Replace { column, with } => fixed[column - 1] = *with,
+                Fix::Insert { column, c } => fixed.insert(column - 1, *c),
+                Fix::Delete { column } => { fixed.remove(column - 1); },
+            }
+            fixed
+        };
+
+        let mut candidates = vec![];
+        if let Some(e) = expected {
+            candidates.push(Fix::Replace { column, with: e });
+            candidates.push(Fix::Insert { column, c: e });
+        }
+        candidates.push(Fix::Delete { column });
+
+        for col in (1..=column).rev() {
+            for d in &self.delimiters {
+                for c in [d.open, d.close] {
+                    candidates.push(Fix::Replace { column: col, with: c });
+                    candidates.push(Fix::Insert { column: col, c });
+                }
+            }
+            candidates.push(Fix::Delete { column: col });
+        }
+
+        candidates.into_iter()
+            .filter(|fix| !matches!(fix, Fix::Replace { column, with } if line[column - 1] == *with))
+            .find(|fix| !self.is_corrupted(&apply(fix)))
     }
+}
+
+fn diagnostic(grammar: &Grammar, line_number: usize, line: &[char]) -> Result<Option<String>, String> {
+    Ok(match grammar.check(line)? {
+        LineStatus::Valid => None,
+        LineStatus::Corrupted { column, expected, found, .. } => {
+            let expected = match expected {
+                Some(e) => format!("expected '{}'", e),
+                None => String::from("nothing to close"),
+            };
+            let fix = match grammar.repair(line) {
+                Some(f) => f.to_string(),
+                None => String::from("no single character fix"),
+            };
+            Some(format!("{}:{}: {}, found '{}' ({})", line_number, column, expected, found, fix))
+        },
+        LineStatus::Incomplete { completion, .. } => {
+            Some(format!("{}:{}: incomplete, complete with '{}'", line_number, line.len() + 1, completion))
+        },
+    })
+}
 
-    if p == 0 {
-        while !stack.is_empty() {
-            score *= 5;
-            score += score_map(closing(stack.pop().unwrap()));
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let grammar = Grammar::standard();
+    let mut report = String::new();
+
+    for (i, val) in contents.lines().enumerate() {
+        if let Some(d) = diagnostic(&grammar, i + 1, &val.chars().collect::<Vec<char>>())? {
+            report.push_str(&d);
+            report.push('\n');
         }
     }
 
-    [p, score]
+    fs::write(dir.join("2021-10.txt"), report).map_err(|e| e.to_string())
 }
 
 pub fn run(contents: &str) -> Result<(), String> {
     print!("[Syntax]...      ");
 
+    let grammar = Grammar::standard();
     let mut points = 0;
     let mut scores = vec![];
 
@@ -79,14 +218,17 @@ pub fn run(contents: &str) -> Result<(), String> {
             continue;
         }
 
-        let [p, s] = line_check(val);
-        points += p;
-
-        if p == 0 {
-            scores.push(s);
+        match grammar.check(&val.chars().collect::<Vec<char>>())? {
+            LineStatus::Valid => {},
+            LineStatus::Corrupted { points: p, .. } => points += p,
+            LineStatus::Incomplete { score, .. } => scores.push(score),
         }
     }
 
+    if scores.is_empty() {
+        return Err(String::from("No incomplete line found"));
+    }
+
     scores.sort();
 
     let middle_score = scores[scores.len() / 2];
@@ -94,4 +236,4 @@ pub fn run(contents: &str) -> Result<(), String> {
     println!("{} {}", points, middle_score);
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,100 +1,139 @@
-use std::cmp::{max, min};
-use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
 
-fn get_neighbors_range(i: usize, j: usize) -> [usize; 4] {
-    let start_i = max(0, i as isize - 1) as usize;
-    let start_j = max(0, j as isize - 1) as usize;
+const FLASH_LEVEL: u8 = 10;
 
-    let end_i = min(i+1, 9);
-    let end_j = min(j+1, 9);
-
-    [start_i, end_i, start_j, end_j]
+#[derive(Clone)]
+pub struct Cavern {
+    octopuses: Vec<Vec<u8>>,
 }
 
-fn process_point(octopuses: &mut Vec<Vec<usize>>, i: usize, j: usize, flashed: &mut BTreeSet<[usize; 2]>) {
-    if octopuses[i][j] < 10 || flashed.contains(&[i,j]) {
-        return;
-    }
-
-    flashed.insert([i,j]);
-    octopuses[i][j] = 0;
+impl Cavern {
+    fn neighbours(&self, pos: [usize; 2]) -> Vec<[usize; 2]> {
+        let mut ret = vec![];
 
-    let r = get_neighbors_range(i, j);
-
-    for x in r[0]..=r[1] {
-        for y in r[2]..=r[3] {
-            if !flashed.contains(&[x, y]) {
-                octopuses[x][y] += 1;
-                process_point(octopuses, i, j, flashed);
+        for x in pos[0].saturating_sub(1)..=pos[0] + 1 {
+            for y in pos[1].saturating_sub(1)..=pos[1] + 1 {
+                if [x, y] != pos && x < self.octopuses.len() && y < self.octopuses[x].len() {
+                    ret.push([x, y]);
+                }
             }
         }
+
+        ret
     }
-}
 
-fn run_step(octopuses: &mut Vec<Vec<usize>>) -> usize {
-    // Increase all values
-    for i in 0..10 {
-        for j in 0..10 {
-            octopuses[i][j] += 1;
-        }
+    pub fn size(&self) -> usize {
+        self.octopuses.iter().map(|row| row.len()).sum()
     }
 
-    let mut flashed: BTreeSet<[usize; 2]> = BTreeSet::new();
-    loop {
-        let f = flashed.len();
-        for i in 0..10 {
-            for j in 0..10 {
-                process_point(octopuses, i, j, &mut flashed);
+    /// Run one step and return the octopuses that flashed, in the order they did.
+    pub fn step(&mut self) -> Vec<[usize; 2]> {
+        let mut flashed = vec![];
+
+        // Increase all values, the ones reaching the flash level start the cascade
+        for i in 0..self.octopuses.len() {
+            for j in 0..self.octopuses[i].len() {
+                self.octopuses[i][j] += 1;
+                if self.octopuses[i][j] == FLASH_LEVEL {
+                    flashed.push([i, j]);
+                }
+            }
+        }
+
+        // Each flash can push neighbours to the flash level, which then flash in turn.
+        // An octopus only joins the queue when it reaches the level, so it flashes once.
+        let mut next = 0;
+        while next < flashed.len() {
+            for [x, y] in self.neighbours(flashed[next]) {
+                if self.octopuses[x][y] < FLASH_LEVEL {
+                    self.octopuses[x][y] += 1;
+                    if self.octopuses[x][y] == FLASH_LEVEL {
+                        flashed.push([x, y]);
+                    }
+                }
             }
+            next += 1;
         }
-        if flashed.len() == f {
-            break;
+
+        for [x, y] in &flashed {
+            self.octopuses[*x][*y] = 0;
         }
+
+        flashed
     }
 
-    flashed.len()
-}
+    /// First step at which every octopus flashes, or None if the cavern enters a cycle that never
+    /// synchronises.
+    pub fn first_sync(&self) -> Option<usize> {
+        let mut cavern = self.clone();
+        let mut seen = HashSet::new();
+        let size = cavern.size();
 
-fn all_flash(octopuses: &Vec<Vec<usize>>) -> bool {
-    for i in 0..10 {
-        if octopuses[i].iter().any(|x| *x != 0) {
-            return false
+        for step in 1.. {
+            if !seen.insert(cavern.octopuses.clone()) {
+                return None;
+            }
+
+            if cavern.step().len() == size {
+                return Some(step);
+            }
         }
+
+        None
     }
-    return true;
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Dumbo]...       ");
+impl FromStr for Cavern {
+    type Err = String;
 
-    let mut flashes = 0;
-    let mut octopuses: Vec<Vec<usize>> = vec![];
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octopuses: Vec<Vec<u8>> = vec![];
 
-    // Load input data
-    for val in contents.lines() {
-        if val.is_empty() {
-            continue;
+        for val in s.lines() {
+            if val.is_empty() {
+                continue;
+            }
+
+            octopuses.push(val.chars()
+                .map(|x| x.to_digit(10).map(|d| d as u8).ok_or(format!("Invalid energy level '{}'", x)))
+                .collect::<Result<Vec<u8>, String>>()?);
         }
 
-        octopuses.push(Vec::from_iter(val.chars().map(|x| x.to_string().parse().unwrap() )));
+        Ok(Cavern { octopuses })
     }
+}
 
-    for _ in 0..100 {
-        let f = run_step(&mut octopuses);
-        flashes += f;
+/// Write the flash order of the first 100 steps, one line per step.
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let mut cavern = Cavern::from_str(contents)?;
+    let mut trace = String::new();
+
+    for step in 1..=100 {
+        let flashed: Vec<String> = cavern.step().iter().map(|[i, j]| format!("{},{}", i, j)).collect();
+        trace.push_str(&format!("{}: {}\n", step, flashed.join(" ")));
     }
 
-    let mut all = 100;
+    fs::write(dir.join("2021-11.txt"), trace).map_err(|e| e.to_string())
+}
 
-    loop {
-        if all_flash(&octopuses) {
-            break;
-        }
-        run_step(&mut octopuses);
-        all += 1;
+pub fn run(contents: &str) -> Result<(), String> {
+    print!("[Dumbo]...       ");
+
+    let start = Cavern::from_str(contents)?;
+    let mut cavern = start.clone();
+    let mut flashes = 0;
+
+    for _ in 0..100 {
+        flashes += cavern.step().len();
     }
 
-    println!("{} {}", flashes, all);
+    match start.first_sync() {
+        Some(all) => println!("{} {}", flashes, all),
+        None => println!("{} never", flashes),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
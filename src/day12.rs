@@ -1,120 +1,418 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::bigint::BigUint;
+use crate::solver::{self, Implementation, Kind, Mode};
+
+const IMPLEMENTATIONS: [Implementation<Policy, BigUint>; 2] = [
+    Implementation { name: "memoised count", kind: Kind::Fast, solve: count_paths },
+    Implementation { name: "enumeration", kind: Kind::Reference, solve: enumerate_paths },
+];
+
+/// Rules of a walk through the caves.
+///
+/// By default small caves can be visited once and big caves any number of times. `visit_limits`
+/// overrides that for some caves, and `extra_visits` more visits can be spread over the caves that
+/// are at their limit, except start and end. A path ends as soon as it reaches the end cave.
+pub struct Policy {
+    pub start: String,
+    pub end: String,
+    pub extra_visits: usize,
+    pub visit_limits: HashMap<String, usize>,
+    /// Links that cannot be used, in either direction
+    pub forbidden: Vec<(String, String)>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            start: String::from("start"),
+            end: String::from("end"),
+            extra_visits: 0,
+            visit_limits: HashMap::new(),
+            forbidden: vec![],
+        }
+    }
+}
 
 struct Cave {
     name: String,
-    neighbours: Vec<Rc<RefCell<Cave>>>
+    neighbours: Vec<usize>,
 }
 
 impl Cave {
-    fn from_name(name: &String) -> Cave {
+    fn from_name(name: &str) -> Cave {
         Cave {
-            name: name.clone(),
+            name: name.to_string(),
             neighbours: vec![]
         }
     }
 
-    fn add_neighbour(&mut self, n: Rc<RefCell<Cave>>) {
-        self.neighbours.push(n);
+    fn is_big(&self) -> bool {
+        self.name.to_ascii_uppercase() == self.name
+    }
+}
+
+pub struct Graph {
+    caves: Vec<Cave>,
+}
+
+// Visit count of a cave with a limit, packed in a u64 with the other ones
+#[derive(Copy, Clone)]
+struct Counter {
+    shift: u32,
+    mask: u64,
+    limit: usize,
+    // Whether the extra visits apply to this cave
+    extra: bool,
+}
+
+impl Counter {
+    fn get(&self, state: u64) -> usize {
+        ((state >> self.shift) & self.mask) as usize
+    }
+
+    fn inc(&self, state: u64) -> u64 {
+        state + (1 << self.shift)
     }
 }
 
-struct Graph {
-    start: Rc<RefCell<Cave>>
+/// A graph and a policy, resolved to cave indices.
+struct Traversal<'a> {
+    caves: &'a [Cave],
+    neighbours: Vec<Vec<usize>>,
+    counters: Vec<Option<Counter>>,
+    start: usize,
+    end: usize,
+    extra_visits: usize,
+}
+
+impl<'a> Traversal<'a> {
+    fn new(graph: &'a Graph, policy: &Policy) -> Result<Traversal<'a>, String> {
+        let start = graph.find(&policy.start)?;
+        let end = graph.find(&policy.end)?;
+
+        let mut forbidden = vec![];
+        for (a, b) in &policy.forbidden {
+            forbidden.push([graph.find(a)?, graph.find(b)?]);
+        }
+
+        let neighbours: Vec<Vec<usize>> = graph.caves.iter().enumerate()
+            .map(|(i, c)| c.neighbours.iter().copied()
+                .filter(|n| !forbidden.contains(&[i, *n]) && !forbidden.contains(&[*n, i]))
+                .collect())
+            .collect();
+
+        let mut counters = vec![None; graph.caves.len()];
+        let mut shift = 0;
+        for (i, c) in graph.caves.iter().enumerate() {
+            let limit = match policy.visit_limits.get(&c.name) {
+                Some(l) => *l,
+                None if i == start || !c.is_big() => 1,
+                None => continue,
+            };
+
+            let extra = i != start && i != end;
+            let max_visits = limit + if extra { policy.extra_visits } else { 0 };
+            // The start is counted once even with a limit of 0
+            let width = usize::BITS - max(max_visits, 1).leading_zeros();
+
+            if shift + width > u64::BITS {
+                return Err(String::from("Too many visit counts to track"));
+            }
+
+            counters[i] = Some(Counter { shift, mask: u64::MAX >> (u64::BITS - width), limit, extra });
+            shift += width;
+        }
+
+        // Going back and forth between caves without a limit would never end
+        for (i, n) in neighbours.iter().enumerate() {
+            if let Some(j) = n.iter().find(|j| counters[i].is_none() && counters[**j].is_none()) {
+                return Err(format!("Caves {} and {} can be visited endlessly",
+                                   graph.caves[i].name, graph.caves[*j].name));
+            }
+        }
+
+        Ok(Traversal {
+            caves: &graph.caves,
+            neighbours,
+            counters,
+            start,
+            end,
+            extra_visits: policy.extra_visits,
+        })
+    }
+
+    // Return whether a cave visited `visits` times can be entered, and if it uses an extra visit
+    fn can_enter(&self, cave: usize, visits: usize, extra_left: usize) -> Option<bool> {
+        match self.counters[cave] {
+            None => Some(false),
+            Some(c) if visits < c.limit => Some(false),
+            Some(c) if c.extra && extra_left > 0 => Some(true),
+            Some(_) => None,
+        }
+    }
+
+    fn count_from(&self, cave: usize, state: u64, extra_left: usize,
+                  memo: &mut HashMap<(usize, u64, usize), BigUint>) -> BigUint {
+        if cave == self.end {
+            return BigUint::from(1);
+        }
+
+        if let Some(c) = memo.get(&(cave, state, extra_left)) {
+            return c.clone();
+        }
+
+        let mut count = BigUint::zero();
+        for &n in &self.neighbours[cave] {
+            let visits = self.counters[n].map_or(0, |c| c.get(state));
+            let left = match self.can_enter(n, visits, extra_left) {
+                Some(false) => extra_left,
+                Some(true) => extra_left - 1,
+                None => continue,
+            };
+
+            // Only once the cave can be entered, the count is then within the width of its field
+            let next_state = self.counters[n].map_or(state, |c| c.inc(state));
+            let paths = self.count_from(n, next_state, left, memo);
+
+            count += &paths;
+        }
+
+        memo.insert((cave, state, extra_left), count.clone());
+        count
+    }
 }
 
 impl Graph {
-    fn get_cave(caves: &mut Vec<Rc<RefCell<Cave>>>, name: &String) -> Rc<RefCell<Cave>> {
-        match caves.iter().find(|x| *x.borrow().name == *name.as_str()) {
-            Some(c) => Rc::clone(c),
+    fn get_cave(caves: &mut Vec<Cave>, name: &str) -> usize {
+        match caves.iter().position(|x| x.name == name) {
+            Some(c) => c,
             None => {
-                let cave = Rc::new(RefCell::new(Cave::from_name(&name)));
-                caves.push(Rc::clone(&cave));
-                cave
+                caves.push(Cave::from_name(name));
+                caves.len() - 1
             }
         }
     }
 
+    fn find(&self, name: &str) -> Result<usize, String> {
+        self.caves.iter().position(|c| c.name == name).ok_or(format!("No cave named {}", name))
+    }
+
     pub fn from_data(graph: &str) -> Result<Graph, String> {
-        let mut caves: Vec<Rc<RefCell<Cave>>> = vec![];
-        let mut start: Option<Rc<RefCell<Cave>>> = None;
+        let mut caves: Vec<Cave> = vec![];
 
         for link in graph.lines() {
-            let linked_caves: Vec<&str> = link.split('-').collect();
-            let cave0 = Graph::get_cave(&mut caves, &String::from(linked_caves[0]));
-            let cave1 = Graph::get_cave(&mut caves, &String::from(linked_caves[1]));
-            cave0.borrow_mut().add_neighbour(Rc::clone(&cave1));
-            cave1.borrow_mut().add_neighbour(Rc::clone(&cave0));
-
-            if cave0.borrow().name == "start" {
-                start = Some(Rc::clone(&cave0));
+            if link.is_empty() {
+                continue;
             }
 
-            if cave1.borrow().name == "start" {
-                start = Some(Rc::clone(&cave1));
+            let linked_caves: Vec<&str> = link.split('-').collect();
+            if linked_caves.len() != 2 {
+                return Err(format!("Invalid link '{}'", link));
             }
-        }
 
-        if let Some(s) = start {
-            Ok(Graph {
-                start: s
-            })
-        } else {
-            Err(String::from("No start node found"))
+            let cave0 = Graph::get_cave(&mut caves, linked_caves[0]);
+            let cave1 = Graph::get_cave(&mut caves, linked_caves[1]);
+            caves[cave0].neighbours.push(cave1);
+            caves[cave1].neighbours.push(cave0);
         }
+
+        Ok(Graph {
+            caves,
+        })
+    }
+
+    /// Number of paths from start to end allowed by the policy.
+    /// Paths are counted by memoising on the current cave, the visit counts so far and the extra
+    /// visits left, so they never have to be built.
+    pub fn count_paths(&self, policy: &Policy) -> Result<BigUint, String> {
+        let t = Traversal::new(self, policy)?;
+        let state = t.counters[t.start].map_or(0, |c| c.inc(0));
+        let mut memo = HashMap::new();
+
+        Ok(t.count_from(t.start, state, t.extra_visits, &mut memo))
     }
 
-    fn is_small_cave(name: &String) -> bool {
-        &name.to_ascii_lowercase() == name &&
-            &name.as_str() != &"start" &&
-            &name.as_str() != &"end"
+    /// Iterate over every path allowed by the policy, each one as the list of the cave names.
+    pub fn paths(&self, policy: &Policy) -> Result<Paths<'_>, String> {
+        let t = Traversal::new(self, policy)?;
+        let mut visits = vec![0; self.caves.len()];
+        visits[t.start] = 1;
+
+        Ok(Paths {
+            stack: vec![PathStep { cave: t.start, next: 0, extra: false }],
+            visits,
+            extra_left: t.extra_visits,
+            traversal: t,
+        })
     }
+}
+
+/// What to emphasize when rendering the graph.
+pub enum Highlight<'a> {
+    None,
+    /// Caves and links of one path
+    Path(&'a [String]),
+    /// How many of the paths allowed by the policy use each link
+    Usage,
+}
+
+impl Graph {
+    /// Graphviz rendering of the caves: start and end are doubled, small caves are ellipses and
+    /// big ones boxes.
+    pub fn to_dot(&self, policy: &Policy, highlight: Highlight) -> Result<String, String> {
+        let start = self.find(&policy.start)?;
+        let end = self.find(&policy.end)?;
+
+        let mut usage: HashMap<[usize; 2], usize> = HashMap::new();
+        let mut on_path = vec![false; self.caves.len()];
+
+        match highlight {
+            Highlight::None => {},
+            Highlight::Path(path) => {
+                let caves = path.iter().map(|n| self.find(n)).collect::<Result<Vec<usize>, String>>()?;
+                for w in caves.windows(2) {
+                    *usage.entry([w[0].min(w[1]), w[0].max(w[1])]).or_insert(0) += 1;
+                }
+                caves.iter().for_each(|c| on_path[*c] = true);
+            },
+            Highlight::Usage => {
+                for path in self.paths(policy)? {
+                    let caves = path.iter().map(|n| self.find(n)).collect::<Result<Vec<usize>, String>>()?;
+                    for w in caves.windows(2) {
+                        *usage.entry([w[0].min(w[1]), w[0].max(w[1])]).or_insert(0) += 1;
+                    }
+                }
+            },
+        }
 
-    fn find_paths(s: &Cave, path: &mut Vec<String>, level: usize, path_list: &mut Vec<Vec<String>>, visited_twice: bool) {
-        path.push(s.name.clone());
+        let max_usage = *usage.values().max().unwrap_or(&1);
+        let mut dot = String::from("graph caves {\n");
 
-        if s.name == String::from("end") {
-            path_list.push(path.clone());
-            path.pop();
-            return;
+        for (i, c) in self.caves.iter().enumerate() {
+            let mut style = vec![format!("shape={}", if c.is_big() { "box" } else { "ellipse" })];
+            if i == start || i == end {
+                style.push(String::from("peripheries=2"));
+                style.push(format!("color={}", if i == start { "darkgreen" } else { "darkred" }));
+            }
+            if on_path[i] {
+                style.push(String::from("style=filled, fillcolor=gold"));
+            }
+            dot.push_str(&format!("    \"{}\" [{}];\n", c.name, style.join(", ")));
         }
-        for n in &s.neighbours {
-            if !path.contains(&n.borrow().name.to_ascii_lowercase()) {
-                Graph::find_paths(&n.borrow(), path, level+1, path_list, visited_twice);
-            } else if Graph::is_small_cave(&n.borrow().name) && !visited_twice {
-                Graph::find_paths(&n.borrow(), path, level+1, path_list, true);
+
+        for (i, c) in self.caves.iter().enumerate() {
+            for &n in c.neighbours.iter().filter(|n| **n > i) {
+                let style = match (usage.get(&[i, n]), &highlight) {
+                    (None, Highlight::None) => String::new(),
+                    (None, _) => String::from(" [color=gray]"),
+                    (Some(u), Highlight::Usage) => format!(" [label={}, penwidth={:.1}]", u,
+                                                           1.0 + 7.0 * *u as f64 / max_usage as f64),
+                    (Some(_), _) => String::from(" [color=gold, penwidth=3]"),
+                };
+                dot.push_str(&format!("    \"{}\" -- \"{}\"{};\n", c.name, self.caves[n].name, style));
             }
         }
 
-        path.pop();
+        dot.push_str("}\n");
+        Ok(dot)
     }
+}
+
+struct PathStep {
+    cave: usize,
+    // Index of the next neighbour to explore
+    next: usize,
+    // Whether getting here used one of the extra visits
+    extra: bool,
+}
+
+/// Depth first enumeration of the paths, see `Graph::paths`.
+pub struct Paths<'a> {
+    traversal: Traversal<'a>,
+    stack: Vec<PathStep>,
+    visits: Vec<usize>,
+    extra_left: usize,
+}
 
-    pub fn find_all_paths(&self, include_double_visits: bool) -> Vec<Vec<String>> {
+impl Iterator for Paths<'_> {
+    type Item = Vec<String>;
 
-        let c = self.start.borrow();
-        let mut path = vec![];
-        let mut path_list = vec![];
-        Graph::find_paths(&c, &mut path, 0, &mut path_list, !include_double_visits);
+    fn next(&mut self) -> Option<Self::Item> {
+        let t = &self.traversal;
 
-        path_list
+        loop {
+            let step = self.stack.last_mut()?;
+
+            if step.next == t.neighbours[step.cave].len() {
+                self.visits[step.cave] -= 1;
+                if step.extra {
+                    self.extra_left += 1;
+                }
+                self.stack.pop();
+                continue;
+            }
+
+            let n = t.neighbours[step.cave][step.next];
+            step.next += 1;
+
+            if n == t.end {
+                let mut path: Vec<String> = self.stack.iter().map(|s| t.caves[s.cave].name.clone()).collect();
+                path.push(t.caves[n].name.clone());
+                return Some(path);
+            }
+
+            let extra = match t.can_enter(n, self.visits[n], self.extra_left) {
+                Some(e) => e,
+                None => continue,
+            };
+
+            if extra {
+                self.extra_left -= 1;
+            }
+            self.visits[n] += 1;
+            self.stack.push(PathStep { cave: n, next: 0, extra });
+        }
     }
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
+fn count_paths(contents: &str, policy: &Policy) -> Result<BigUint, String> {
+    Graph::from_data(contents)?.count_paths(policy)
+}
+
+fn enumerate_paths(contents: &str, policy: &Policy) -> Result<BigUint, String> {
+    Ok(BigUint::from(Graph::from_data(contents)?.paths(policy)?.count() as u64))
+}
+
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let graph = Graph::from_data(contents)?;
+    let policy = Policy::default();
+
+    fs::write(dir.join("2021-12.dot"), graph.to_dot(&policy, Highlight::None)?)
+        .map_err(|e| e.to_string())?;
+    fs::write(dir.join("2021-12-usage.dot"), graph.to_dot(&policy, Highlight::Usage)?)
+        .map_err(|e| e.to_string())?;
+
+    if let Some(path) = graph.paths(&policy)?.next() {
+        fs::write(dir.join("2021-12-path.dot"), graph.to_dot(&policy, Highlight::Path(&path))?)
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+pub fn run(contents: &str, mode: Mode) -> Result<(), String> {
     print!("[Caves]...       ");
 
-    // Load input data
-    let graph = match Graph::from_data(contents) {
-        Ok(g) => g,
-        Err(e) => {
-            println!("{}", e);
-            return Err(e);
-        }
-    };
-    let paths1 = graph.find_all_paths(false);
-    let paths2 = graph.find_all_paths(true);
+    let part1 = Policy::default();
+    let part2 = Policy { extra_visits: 1, ..Policy::default() };
 
-    println!("{} {}", paths1.len(), paths2.len());
+    let paths1 = solver::solve(&IMPLEMENTATIONS, mode, contents, &part1)?;
+    let paths2 = solver::solve(&IMPLEMENTATIONS, mode, contents, &part2)?;
+
+    println!("{} {}", paths1, paths2);
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,49 +1,167 @@
 use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
+#[derive(Copy, Clone)]
 enum Fold {
     X(usize),
     Y(usize)
 }
 
 impl Fold {
-    pub fn from_str(text: &str) -> Fold {
-        let (_, info) = text.split_at(10);
+    fn axis(&self) -> (usize, usize) {
+        match self {
+            Fold::X(v) => (0, *v),
+            Fold::Y(v) => (1, *v),
+        }
+    }
+
+    pub fn apply_point(&self, p: &Point) -> Point {
+        let (p_index, val) = self.axis();
+        let mut new_p = *p;
+
+        if p.p[p_index] > val {
+            new_p.p[p_index] = val - (p.p[p_index] - val);
+        }
+
+        new_p
+    }
+
+    pub fn apply(&self, points: &BTreeSet<Point>) -> BTreeSet<Point> {
+        points.iter().map(|p| self.apply_point(p)).collect()
+    }
+
+    /// Paper size once folded.
+    pub fn apply_size(&self, size: [usize; 2]) -> [usize; 2] {
+        let (p_index, val) = self.axis();
+        let mut new_size = size;
+        new_size[p_index] = val;
+
+        new_size
+    }
+}
+
+impl FromStr for Fold {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let info = match text.strip_prefix("fold along ") {
+            Some(i) => i,
+            None => return Err(format!("Invalid fold '{}'", text)),
+        };
         let parts: Vec<&str> = info.trim().split('=').collect();
+        let val = match parts.get(1).map(|v| v.parse()) {
+            Some(Ok(v)) => v,
+            _ => return Err(format!("Invalid fold '{}'", text)),
+        };
+
         match parts[0] {
-            "x" => Fold::X(parts[1].parse().unwrap()),
-            _ => Fold::Y(parts[1].parse().unwrap())
+            "x" => Ok(Fold::X(val)),
+            "y" => Ok(Fold::Y(val)),
+            _ => Err(format!("Invalid fold axis '{}'", parts[0])),
         }
     }
+}
 
-    pub fn apply(&self, points: &BTreeSet<Point>) -> BTreeSet<Point> {
-        let mut new_vec: BTreeSet<Point> = BTreeSet::new(); //TODO
-        let p_index;
-        let val;
+/// State of the paper after a fold.
+pub struct FoldRecord {
+    pub dots: usize,
+    pub size: [usize; 2],
+    /// Dots that landed on another one
+    pub overlaps: usize,
+}
 
-        match self {
-            Fold::X(v) => {
-                p_index = 0;
-                val = v;
-            },
-            Fold::Y(v) => {
-                p_index = 1;
-                val = v;
-            }
+struct Paper {
+    points: BTreeSet<Point>,
+    folds: Vec<Fold>,
+}
+
+impl Paper {
+    fn size(&self) -> [usize; 2] {
+        [self.points.iter().map(|p| p.p[0] + 1).max().unwrap_or(0),
+         self.points.iter().map(|p| p.p[1] + 1).max().unwrap_or(0)]
+    }
+
+    /// Apply every fold, and return the final dots with the record of each fold.
+    pub fn fold_all(&self) -> (BTreeSet<Point>, Vec<FoldRecord>) {
+        let mut points = self.points.clone();
+        let mut size = self.size();
+        let mut history = vec![];
+
+        for f in &self.folds {
+            let folded = f.apply(&points);
+            size = f.apply_size(size);
+
+            history.push(FoldRecord {
+                dots: folded.len(),
+                size,
+                overlaps: points.len() - folded.len(),
+            });
+            points = folded;
+        }
+
+        (points, history)
+    }
+
+    /// Dots and paper size after the first `folds` folds.
+    pub fn stage(&self, folds: usize) -> (BTreeSet<Point>, [usize; 2]) {
+        let mut points = self.points.clone();
+        let mut size = self.size();
+
+        for f in self.folds.iter().take(folds) {
+            points = f.apply(&points);
+            size = f.apply_size(size);
         }
-        for p in points {
-            if p.p[p_index] > *val {
-                let mut new_p: Point = p.clone();
-                new_p.p[p_index] = *val - (p.p[p_index] - *val);
-                new_vec.insert(Point {p: new_p.p});
+
+        (points, size)
+    }
+
+    /// For each final dot, the original dots that were folded onto it.
+    pub fn unfold(&self) -> BTreeMap<Point, Vec<Point>> {
+        let mut origins: BTreeMap<Point, Vec<Point>> = BTreeMap::new();
+
+        for p in &self.points {
+            let folded = self.folds.iter().fold(*p, |q, f| f.apply_point(&q));
+            origins.entry(folded).or_default().push(*p);
+        }
+
+        origins
+    }
+}
+
+impl FromStr for Paper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut points = BTreeSet::new();
+        let mut folds = vec![];
+
+        for val in s.lines() {
+            if val.is_empty() {
+                continue;
             }
-            else {
-                new_vec.insert(p.clone());
+
+            if val.starts_with("fold") {
+                folds.push(Fold::from_str(val)?);
+            } else {
+                points.insert(Point::from_str(val)?);
             }
         }
 
-        new_vec
+        // A dot further past the fold line than the line is from the edge would end up before it
+        let mut folded = points.clone();
+        for f in &folds {
+            let (axis, val) = f.axis();
+            if let Some(p) = folded.iter().find(|p| p.p[axis] > 2 * val) {
+                return Err(format!("Fold along {}={} sends the dot {},{} past the edge",
+                                   ["x", "y"][axis], val, p.p[0], p.p[1]));
+            }
+            folded = f.apply(&folded);
+        }
+
+        Ok(Paper { points, folds })
     }
 }
 
@@ -58,7 +176,13 @@ impl FromStr for Point {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let vals: Vec<&str> = s.split(',').collect();
 
-        Ok(Point{p: [vals[0].parse().unwrap(), vals[1].parse().unwrap()]})
+        match vals[..] {
+            [x, y] => match (x.trim().parse(), y.trim().parse()) {
+                (Ok(x), Ok(y)) => Ok(Point{p: [x, y]}),
+                _ => Err(format!("Invalid point '{}'", s)),
+            },
+            _ => Err(format!("Invalid point '{}'", s)),
+        }
     }
 }
 
@@ -71,8 +195,8 @@ impl PartialEq<Self> for Point {
 }
 
 impl PartialOrd<Self> for Point {
-    fn partial_cmp(&self, _other: &Self) -> Option<Ordering> {
-        todo!()
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -94,57 +218,131 @@ impl Ord for Point {
     }
 }
 
-fn print_points(points: &BTreeSet<Point>) {
-    let mut max_x:usize = 0;
-    let mut max_y:usize = 0;
-    for p in points {
-        if max_x < p.p[0] {
-            max_x = p.p[0];
-        }
-        if max_y < p.p[1] {
-            max_y = p.p[1];
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+// Glyphs are separated by an empty column
+const GLYPH_SPACING: usize = GLYPH_WIDTH + 1;
+
+const FONT: [(char, [&str; GLYPH_HEIGHT]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+/// Read the capital letters drawn by the points, in the 4x6 font of the puzzle.
+fn ocr(points: &BTreeSet<Point>) -> Result<String, String> {
+    let max_x = points.iter().map(|p| p.p[0]).max().unwrap_or(0);
+    let max_y = points.iter().map(|p| p.p[1]).max().unwrap_or(0);
+
+    if points.is_empty() || max_y >= GLYPH_HEIGHT {
+        return Err(format!("Expected a single line of {} pixels high letters", GLYPH_HEIGHT));
+    }
+
+    let mut text = String::new();
+
+    for i in 0..=max_x / GLYPH_SPACING {
+        let block: Vec<String> = (0..GLYPH_HEIGHT)
+            .map(|y| (0..GLYPH_WIDTH)
+                .map(|x| match points.contains(&Point { p: [i * GLYPH_SPACING + x, y] }) {
+                    true => '#',
+                    false => '.',
+                })
+                .collect())
+            .collect();
+
+        match FONT.iter().find(|(_, glyph)| glyph.iter().zip(&block).all(|(a, b)| a == b)) {
+            Some((c, _)) => text.push(*c),
+            None => return Err(format!("Unrecognised glyph {}:\n{}", i + 1, block.join("\n"))),
         }
     }
 
-    for y in 0..=max_y {
-        for x in 0..=max_x {
-            if points.contains(&Point{p:[x,y]}) {
-                print!("#");
-            } else {
-                print!(" ");
+    Ok(text)
+}
+
+/// Binary PBM (P4) of the paper, each dot being a `scale` x `scale` black square.
+fn render_pbm(points: &BTreeSet<Point>, size: [usize; 2], scale: usize) -> Vec<u8> {
+    let [width, height] = [size[0] * scale, size[1] * scale];
+    let mut image = format!("P4\n{} {}\n", width, height).into_bytes();
+
+    for y in 0..height {
+        // Rows are padded to a full byte
+        let mut row = vec![0u8; width.div_ceil(8)];
+        for x in 0..width {
+            if points.contains(&Point { p: [x / scale, y / scale] }) {
+                row[x / 8] |= 0x80 >> (x % 8);
             }
         }
-        println!();
+        image.extend_from_slice(&row);
     }
+
+    image
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Origami]...     ");
+fn render_svg(points: &BTreeSet<Point>, size: [usize; 2], scale: usize) -> String {
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+                          size[0] * scale, size[1] * scale);
+    svg.push_str("  <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
 
-    let mut points = BTreeSet::new();
-    let mut first_fold: Option<usize> = None;
-    //let mut folds = vec![];
+    for p in points {
+        svg.push_str(&format!("  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                              p.p[0] * scale, p.p[1] * scale, scale, scale));
+    }
 
-    // Load input data
-    for val in contents.lines() {
-        if val.is_empty() {
-            continue;
-        }
+    svg.push_str("</svg>\n");
+    svg
+}
 
-        if val.starts_with("fold") {
-            points = Fold::from_str(val).apply(&points);
-            first_fold = match first_fold {
-                Some(t) => Some(t),
-                None => Some(points.len())
-            };
-        } else {
-            points.insert(Point::from_str(val).unwrap());
-        }
+/// Write the record of each fold, then the original dots behind each final one.
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let paper = Paper::from_str(contents)?;
+    let (_, history) = paper.fold_all();
+    let mut report = String::new();
 
+    for (i, r) in history.iter().enumerate() {
+        report.push_str(&format!("fold {}: {} dots, {}x{}, {} overlaps\n",
+                                 i + 1, r.dots, r.size[0], r.size[1], r.overlaps));
     }
 
-    println!("{}", first_fold.unwrap());
-    print_points(&points);
+    for (dot, origins) in paper.unfold() {
+        let origins: Vec<String> = origins.iter().map(|p| format!("{},{}", p.p[0], p.p[1])).collect();
+        report.push_str(&format!("{},{} <- {}\n", dot.p[0], dot.p[1], origins.join(" ")));
+    }
+
+    fs::write(dir.join("2021-13.txt"), report).map_err(|e| e.to_string())?;
+
+    let (points, size) = paper.stage(history.len());
+    fs::write(dir.join("2021-13.pbm"), render_pbm(&points, size, 4)).map_err(|e| e.to_string())?;
+    fs::write(dir.join("2021-13.svg"), render_svg(&points, size, 10)).map_err(|e| e.to_string())
+}
+
+pub fn run(contents: &str) -> Result<String, String> {
+    print!("[Origami]...     ");
 
-    Ok(())
-}
\ No newline at end of file
+    let paper = Paper::from_str(contents)?;
+    let (points, history) = paper.fold_all();
+
+    let first_fold = match history.first() {
+        Some(r) => r.dots,
+        None => return Err(String::from("No fold found")),
+    };
+
+    let code = ocr(&points)?;
+
+    println!("{} {}", first_fold, code);
+
+    Ok(code)
+}
@@ -1,128 +1,339 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
+use crate::bigint::BigUint;
+use crate::matrix::{self, Matrix};
+use crate::solver::{self, Implementation, Kind, Mode};
 
-struct Rule {
-    from: Vec<char>,
-    to: char
-}
+const IMPLEMENTATIONS: [Implementation<u64, BigUint>; 2] = [
+    Implementation { name: "pair counts", kind: Kind::Fast, solve: solve_by_steps },
+    Implementation { name: "matrix power", kind: Kind::Reference, solve: solve_by_matrix },
+];
 
-impl Rule {
-    pub fn apply(&self, k: &String) -> Option<[Vec<char>; 2]> {
-        let key: Vec<char> = k.chars().collect();
-        if self.from == key {
-            return Some([vec![self.from[0], self.to], vec![self.to, self.from[1]]]);
-        }
+const ELEMENTS: usize = 26;
 
-        None
+// Puzzle example, small enough to cross-check the implementations at every step
+const EXAMPLE: &str = "NNCB
+
+CH -> B
+HH -> N
+CB -> H
+NH -> C
+HB -> C
+HC -> B
+HN -> C
+NN -> C
+BH -> H
+NC -> B
+NB -> B
+BN -> B
+BB -> N
+BC -> B
+CC -> N
+CN -> C
+";
+
+fn element(c: char) -> Result<usize, String> {
+    match c {
+        'A'..='Z' => Ok(c as usize - 'A' as usize),
+        _ => Err(format!("Invalid element '{}'", c)),
     }
 }
 
+fn element_name(e: usize) -> char {
+    (b'A' + e as u8) as char
+}
+
+fn pair_index(a: usize, b: usize) -> usize {
+    a * ELEMENTS + b
+}
+
+struct Rule {
+    from: [char; 2],
+    to: char
+}
+
 impl FromStr for Rule {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let vals: Vec<&str> = s.split(" -> ").collect();
-        let base = vals[0];
+        let from: Vec<char> = vals[0].chars().collect();
+        let to: Vec<char> = vals.get(1).map_or(vec![], |v| v.chars().collect());
 
-        let mut to = base.to_string().clone();
-        to.insert_str(1, vals[1]);
-
-        let to_char: Vec<char> = vals[1].chars().collect();
+        if vals.len() != 2 || from.len() != 2 || to.len() != 1 {
+            return Err(format!("Invalid rule '{}'", s));
+        }
 
         Ok(Rule {
-            from: base.chars().collect(),
-            to: to_char[0],
+            from: [from[0], from[1]],
+            to: to[0],
         })
     }
 }
 
 impl Display for Rule {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} -> {}", self.from, self.to)
+        write!(f, "{}{} -> {}", self.from[0], self.from[1], self.to)
     }
 }
 
-fn count_letters(template: &Vec<char>, rules: &Vec<Rule>, steps: usize) -> usize {
-    let mut map: HashMap<String, usize> = HashMap::new();
-    for i in 0..template.len()-1 {
-        map.insert(String::from_iter(&template[i..i+2]), 1);
+/// Insertion rules, indexed by pair.
+struct RuleTable {
+    insert: Vec<Option<usize>>,
+}
+
+impl RuleTable {
+    fn from_rules(rules: &[Rule]) -> Result<RuleTable, String> {
+        let mut insert = vec![None; ELEMENTS * ELEMENTS];
+
+        for r in rules {
+            let pair = pair_index(element(r.from[0])?, element(r.from[1])?);
+            if insert[pair].is_some() {
+                return Err(format!("Several rules for {}{}", r.from[0], r.from[1]));
+            }
+            insert[pair] = Some(element(r.to)?);
+        }
+
+        Ok(RuleTable { insert })
+    }
+}
+
+/// Most and least common elements of the polymer after a step.
+pub struct StepStats {
+    pub step: usize,
+    pub length: u64,
+    pub most: (char, u64),
+    pub least: (char, u64),
+}
+
+/// The polymer as a count of each pair of adjacent elements.
+/// The last element is never the first of a pair, so it is kept aside to count elements.
+struct Polymer<'a> {
+    rules: &'a RuleTable,
+    pairs: Vec<u64>,
+    last: usize,
+    step: usize,
+    overflow: bool,
+}
+
+impl<'a> Polymer<'a> {
+    fn new(template: &[char], rules: &'a RuleTable) -> Result<Polymer<'a>, String> {
+        let elements = template.iter().map(|c| element(*c)).collect::<Result<Vec<usize>, String>>()?;
+        let mut pairs = vec![0; ELEMENTS * ELEMENTS];
+
+        let last = match elements.last() {
+            Some(l) => *l,
+            None => return Err(String::from("Empty template")),
+        };
+
+        for w in elements.windows(2) {
+            pairs[pair_index(w[0], w[1])] += 1;
+        }
+
+        Ok(Polymer { rules, pairs, last, step: 0, overflow: false })
     }
 
-    let mut new_map: HashMap<String, usize> = map.clone();
+    // Return false if a count overflowed
+    fn grow(&mut self) -> bool {
+        let mut new_pairs: Vec<u64> = vec![0; ELEMENTS * ELEMENTS];
+        let mut ok = true;
+        let mut add = |pair: usize, count: u64| {
+            match new_pairs[pair].checked_add(count) {
+                Some(c) => new_pairs[pair] = c,
+                None => ok = false,
+            }
+        };
 
-    for _ in 0..steps {
-        for (k, v) in &map {
-            if *v == 0 {
+        for (pair, count) in self.pairs.iter().enumerate() {
+            if *count == 0 {
                 continue;
             }
-            for r in rules {
-                match &r.apply(k) {
-                    None => {}
-                    Some(a) => {
-                        let key1 = String::from_iter(a[0].iter());
-                        let key2 = String::from_iter(a[1].iter());
-
-                        // Add new elements
-                        match new_map.get(&key1) {
-                            None => {
-                                new_map.insert(key1, *v);
-                            },
-                            Some(n) => {
-                                let new_n = *n;
-                                new_map.insert(key1, new_n + *v);
-                            },
-                        };
-
-                        match new_map.get(&key2) {
-                            None => {
-                                new_map.insert(key2, *v);
-                            },
-                            Some(n) => {
-                                let new_n = *n;
-                                new_map.insert(key2, new_n + *v);
-                            },
-                        };
-
-                        // Decrease this key
-                        if let Some(n) = new_map.get(k) {
-                            let new_n = *n;
-                            new_map.insert(k.clone(), new_n - *v);
-                        }
-                    }
-                }
+
+            let (a, b) = (pair / ELEMENTS, pair % ELEMENTS);
+            match self.rules.insert[pair] {
+                Some(c) => {
+                    add(pair_index(a, c), *count);
+                    add(pair_index(c, b), *count);
+                },
+                None => add(pair, *count),
             }
         }
 
-        map = new_map.clone();
+        // The element counts add up to the length, so they fit if it does
+        let length = new_pairs.iter().try_fold(1u64, |acc, c| acc.checked_add(*c));
+
+        self.pairs = new_pairs;
+        self.step += 1;
+        ok && length.is_some()
     }
 
-    let mut counts: HashMap<char, usize> = HashMap::new();
+    fn element_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0; ELEMENTS];
 
-    for c in 'A'..'Z' {
-        counts.insert(c, 0);
+        for (pair, count) in self.pairs.iter().enumerate() {
+            counts[pair / ELEMENTS] += count;
+        }
+        counts[self.last] += 1;
+
+        counts
     }
 
-    for (x, y) in &map {
-        for c in x.chars() {
-            if let Some(v) = counts.get_mut(&c) {
-                *v += *y;
-            }
+    fn stats(&self) -> StepStats {
+        let counts = self.element_counts();
+        let present = || counts.iter().enumerate().filter(|(_, c)| **c > 0);
+        let (most, most_count) = present().max_by_key(|(_, c)| **c).unwrap();
+        let (least, least_count) = present().min_by_key(|(_, c)| **c).unwrap();
+
+        StepStats {
+            step: self.step,
+            length: counts.iter().sum(),
+            most: (element_name(most), *most_count),
+            least: (element_name(least), *least_count),
         }
     }
+}
+
+/// Statistics of each step, starting with the template itself at step 0.
+/// Stops when the counts no longer fit in 64 bits.
+impl Iterator for Polymer<'_> {
+    type Item = StepStats;
 
-    counts.retain(|_, &mut v| v > 0);
-    counts.iter_mut().for_each(|(_, b)| *b = (*b as f64 / 2.0).ceil() as usize);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.overflow {
+            return None;
+        }
 
-    let max = counts.iter().max_by(|a,b| a.1.cmp(b.1)).unwrap();
-    let min = counts.iter().min_by(|a,b| a.1.cmp(b.1)).unwrap();
+        let stats = self.stats();
+        self.overflow = !self.grow();
 
-    max.1 - min.1
+        Some(stats)
+    }
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Poly]...        ");
+fn count_letters(template: &[char], rules: &RuleTable, steps: usize) -> Result<u64, String> {
+    match Polymer::new(template, rules)?.nth(steps) {
+        Some(s) => Ok(s.most.1 - s.least.1),
+        None => Err(format!("Element counts overflow before {} steps", steps)),
+    }
+}
 
+/// One step as a linear map on the counts of the pairs that can appear from the template:
+/// matrix[to][from] is how many `to` pairs one `from` pair becomes.
+struct PairTransitions {
+    pairs: Vec<usize>,
+    matrix: Matrix<u64>,
+    initial: Vec<u64>,
+    last: usize,
+}
+
+impl PairTransitions {
+    fn new(template: &[char], rules: &RuleTable) -> Result<PairTransitions, String> {
+        let polymer = Polymer::new(template, rules)?;
+        let produced = |pair: usize| -> Vec<usize> {
+            let (a, b) = (pair / ELEMENTS, pair % ELEMENTS);
+            match rules.insert[pair] {
+                Some(c) => vec![pair_index(a, c), pair_index(c, b)],
+                None => vec![pair],
+            }
+        };
+
+        // Only keep the pairs reachable from the template to keep the matrix small
+        let mut pairs: Vec<usize> = (0..ELEMENTS * ELEMENTS).filter(|p| polymer.pairs[*p] > 0).collect();
+        let mut next = 0;
+        while next < pairs.len() {
+            for p in produced(pairs[next]) {
+                if !pairs.contains(&p) {
+                    pairs.push(p);
+                }
+            }
+            next += 1;
+        }
+
+        let mut m = vec![vec![0; pairs.len()]; pairs.len()];
+        for (from, pair) in pairs.iter().enumerate() {
+            for p in produced(*pair) {
+                let to = pairs.iter().position(|x| *x == p).unwrap();
+                m[to][from] += 1;
+            }
+        }
+
+        Ok(PairTransitions {
+            initial: pairs.iter().map(|p| polymer.pairs[*p]).collect(),
+            pairs,
+            matrix: m,
+            last: polymer.last,
+        })
+    }
+
+    fn next_present(&self, present: &[bool]) -> Vec<bool> {
+        (0..self.pairs.len())
+            .map(|to| (0..self.pairs.len()).any(|from| present[from] && self.matrix[to][from] > 0))
+            .collect()
+    }
+
+    /// Pairs present after `steps` steps. This only depends on the pairs present the step before,
+    /// so the sequence ends up repeating and long runs skip the repeats.
+    fn present_pairs(&self, steps: u64) -> Vec<bool> {
+        let mut present: Vec<bool> = self.initial.iter().map(|c| *c > 0).collect();
+        let mut seen = HashMap::new();
+        let mut step = 0;
+
+        while step < steps {
+            if let Some(start) = seen.insert(present.clone(), step) {
+                for _ in 0..(steps - step) % (step - start) {
+                    present = self.next_present(&present);
+                }
+                return present;
+            }
+
+            present = self.next_present(&present);
+            step += 1;
+        }
+
+        present
+    }
+
+    // Sum the pair counts into element counts, for the elements present after `steps` steps.
+    // A modular count can be 0 for a present element, so presence is not read from the counts.
+    fn element_counts<T: Clone>(&self, steps: u64, pair_counts: &[T], zero: T, one: T,
+                                add: impl Fn(&T, &T) -> T) -> Vec<(char, T)> {
+        let mut counts: Vec<Option<T>> = vec![None; ELEMENTS];
+        let present = self.present_pairs(steps);
+
+        for ((pair, count), _) in self.pairs.iter().zip(pair_counts).zip(&present).filter(|(_, p)| **p) {
+            let e = &mut counts[pair / ELEMENTS];
+            *e = Some(add(e.as_ref().unwrap_or(&zero), count));
+        }
+        let e = &mut counts[self.last];
+        *e = Some(add(e.as_ref().unwrap_or(&zero), &one));
+
+        counts.into_iter().enumerate()
+            .filter_map(|(i, c)| c.map(|c| (element_name(i), c)))
+            .collect()
+    }
+
+    /// Element counts after `steps` steps, modulo `modulus`.
+    pub fn element_counts_mod(&self, steps: u64, modulus: u64) -> Vec<(char, u64)> {
+        let p = matrix::pow_mod(&self.matrix, steps, modulus);
+        let pair_counts = matrix::apply_mod(&p, &self.initial, modulus);
+
+        self.element_counts(steps, &pair_counts, 0, 1 % modulus,
+                            |a, b| ((*a as u128 + *b as u128) % modulus as u128) as u64)
+    }
+
+    /// Exact element counts after `steps` steps.
+    pub fn element_counts_exact(&self, steps: u64) -> Vec<(char, BigUint)> {
+        let p = matrix::pow_big(&self.matrix, steps);
+        let pair_counts = matrix::apply_big(&p, &self.initial);
+
+        self.element_counts(steps, &pair_counts, BigUint::zero(), BigUint::from(1), |a, b| a + b)
+    }
+}
+
+fn load(contents: &str) -> Result<(Vec<char>, RuleTable), String> {
     let mut template: Vec<char> = vec![];
     let mut rules = vec![];
 
@@ -135,13 +346,66 @@ pub fn run(contents: &str) -> Result<(), String> {
         if template.is_empty() {
             template = val.chars().collect();
         } else {
-            rules.push(Rule::from_str(val).unwrap());
+            rules.push(Rule::from_str(val)?);
         }
 
     }
 
-    print!("{} ", count_letters(&template, &rules, 10));
-    println!("{}", count_letters(&template, &rules, 40));
+    Ok((template, RuleTable::from_rules(&rules)?))
+}
+
+/// Write the statistics of the first 40 steps, then the element counts for much larger ones.
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let (template, table) = load(contents)?;
+    let mut report = String::new();
+
+    for s in Polymer::new(&template, &table)?.take(41) {
+        report.push_str(&format!("{}: length {}, most {} ({}), least {} ({})\n",
+                                 s.step, s.length, s.most.0, s.most.1, s.least.0, s.least.1));
+    }
+
+    let transitions = PairTransitions::new(&template, &table)?;
+    let format_counts = |counts: Vec<(char, String)>| -> String {
+        counts.iter().map(|(e, c)| format!("{}={}", e, c)).collect::<Vec<String>>().join(" ")
+    };
+
+    let exact = transitions.element_counts_exact(100).into_iter().map(|(e, c)| (e, c.to_string())).collect();
+    report.push_str(&format!("100: {}\n", format_counts(exact)));
+
+    let modulo = transitions.element_counts_mod(1_000_000_000_000, 1_000_000_007).into_iter()
+        .map(|(e, c)| (e, c.to_string())).collect();
+    report.push_str(&format!("10^12 (mod 1000000007): {}\n", format_counts(modulo)));
+
+    fs::write(dir.join("2021-14.txt"), report).map_err(|e| e.to_string())
+}
+
+fn solve_by_steps(contents: &str, steps: &u64) -> Result<BigUint, String> {
+    let (template, table) = load(contents)?;
+
+    Ok(BigUint::from(count_letters(&template, &table, *steps as usize)?))
+}
+
+fn solve_by_matrix(contents: &str, steps: &u64) -> Result<BigUint, String> {
+    let (template, table) = load(contents)?;
+    let counts = PairTransitions::new(&template, &table)?.element_counts_exact(*steps);
+
+    let most = counts.iter().map(|(_, c)| c).max().unwrap();
+    let least = counts.iter().map(|(_, c)| c).min().unwrap();
+
+    Ok(most.checked_sub(least).unwrap())
+}
+
+pub fn run(contents: &str, mode: Mode) -> Result<(), String> {
+    print!("[Poly]...        ");
+
+    if mode == Mode::Check {
+        for steps in 0..=10 {
+            solver::solve(&IMPLEMENTATIONS, mode, EXAMPLE, &steps)?;
+        }
+    }
+
+    print!("{} ", solver::solve(&IMPLEMENTATIONS, mode, contents, &10)?);
+    println!("{}", solver::solve(&IMPLEMENTATIONS, mode, contents, &40)?);
 
     Ok(())
-}
\ No newline at end of file
+}
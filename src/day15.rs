@@ -1,33 +1,102 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
 
-fn find_neighbors(cases: &Vec<Vec<usize>>, i: usize, j: usize) -> Vec<[usize; 2]> {
-    let mut points = vec![];
-    if i > 0 {
-        points.push([i-1, j]);
+/// Risk levels of the cave. The map can be repeated `tiles` times in both directions, each repeat
+/// adding 1 to the risk levels (wrapping 9 back to 1), without building the larger map.
+pub struct RiskMap {
+    cases: Vec<Vec<usize>>,
+    tiles: usize,
+}
+
+/// How the path can move from one position to the next.
+#[derive(Copy, Clone, Debug)]
+pub enum Movement {
+    /// Up, down, left or right
+    Orthogonal,
+    /// Diagonals as well
+    AllDirections,
+    /// Like a chess knight
+    Knight,
+}
+
+impl Movement {
+    const ALL: [Movement; 3] = [Movement::Orthogonal, Movement::AllDirections, Movement::Knight];
+
+    fn offsets(&self) -> &'static [[isize; 2]] {
+        match self {
+            Movement::Orthogonal => &[[-1, 0], [0, -1], [1, 0], [0, 1]],
+            Movement::AllDirections => &[[-1, -1], [-1, 0], [-1, 1], [0, -1], [0, 1], [1, -1], [1, 0], [1, 1]],
+            Movement::Knight => &[[-2, -1], [-2, 1], [-1, -2], [-1, 2], [1, -2], [1, 2], [2, -1], [2, 1]],
+        }
     }
-    if j > 0 {
-        points.push([i, j-1]);
+
+    /// Lower bound of the number of moves between two positions.
+    fn min_moves(&self, from: [usize; 2], to: [usize; 2]) -> usize {
+        let dx = from[0].abs_diff(to[0]);
+        let dy = from[1].abs_diff(to[1]);
+
+        match self {
+            Movement::Orthogonal => dx + dy,
+            Movement::AllDirections => dx.max(dy),
+            // A knight move covers at most 2 on one axis and 3 in total
+            Movement::Knight => dx.div_ceil(2).max(dy.div_ceil(2)).max((dx + dy).div_ceil(3)),
+        }
     }
-    if i < cases.len() - 1 {
-        points.push([i+1, j]);
+}
+
+/// What a move costs.
+#[derive(Copy, Clone, Debug)]
+pub enum Cost {
+    /// Risk level of the position entered
+    Enter,
+    /// Risk level of the position left
+    Exit,
+    /// Difference between the risk levels of both positions
+    Elevation,
+}
+
+impl Cost {
+    const ALL: [Cost; 3] = [Cost::Enter, Cost::Exit, Cost::Elevation];
+
+    fn step(&self, map: &RiskMap, from: [usize; 2], to: [usize; 2]) -> usize {
+        match self {
+            Cost::Enter => map.risk(to),
+            Cost::Exit => map.risk(from),
+            Cost::Elevation => map.risk(from).abs_diff(map.risk(to)),
+        }
     }
-    if j < cases.len() - 1 {
-        points.push([i, j+1]);
+
+    /// Admissible estimate of the cost left, for A*.
+    fn lower_bound(&self, map: &RiskMap, movement: Movement, from: [usize; 2], goal: [usize; 2]) -> usize {
+        match self {
+            // Every move costs at least 1
+            Cost::Enter | Cost::Exit => movement.min_moves(from, goal),
+            // The differences along the way add up to at least the difference between both ends
+            Cost::Elevation => map.risk(from).abs_diff(map.risk(goal)),
+        }
     }
+}
 
-    points
+/// Lowest total cost to the bottom right corner, and the positions on the way, start included.
+pub struct Route {
+    pub cost: usize,
+    pub path: Vec<[usize; 2]>,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 struct State {
+    // Cost so far plus the estimate of what is left
+    priority: usize,
     cost: usize,
     position: [usize; 2],
 }
 
 impl Ord for State {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.cost.cmp(&self.cost)
+        other.priority.cmp(&self.priority)
             .then_with(|| self.position.cmp(&other.position))
     }
 }
@@ -38,72 +107,169 @@ impl PartialOrd for State {
     }
 }
 
-fn find_path(cases: &Vec<Vec<usize>>) -> Option<usize> {
-    let mut dist: Vec<Vec<usize>> = (0..cases.len()).map(|_| (0..cases.len()).map(|_| usize::MAX).collect() ).collect();
-    let mut heap = BinaryHeap::new();
+impl RiskMap {
+    pub fn tiled(&self, tiles: usize) -> RiskMap {
+        RiskMap {
+            cases: self.cases.clone(),
+            tiles,
+        }
+    }
 
-    dist[0][0] = 0;
-    heap.push(State { cost: 0, position: [0, 0] });
+    /// [rows, columns]
+    pub fn size(&self) -> [usize; 2] {
+        [self.cases.len() * self.tiles, self.cases[0].len() * self.tiles]
+    }
 
-    while let Some(State { cost, position }) = heap.pop() {
-        if position == [cases.len() - 1, cases.len() - 1] {
-            return Some(cost);
-        }
+    pub fn risk(&self, pos: [usize; 2]) -> usize {
+        let [rows, cols] = [self.cases.len(), self.cases[0].len()];
+        let base = self.cases[pos[0] % rows][pos[1] % cols];
 
-        if cost > dist[position[0]][position[1]] {
-            continue;
-        }
+        (base - 1 + pos[0] / rows + pos[1] / cols) % 9 + 1
+    }
 
-        for n in find_neighbors(cases, position[0], position[1]) {
-            let next = State { cost: cost + cases[n[0]][n[1]], position: n };
+    fn find_neighbors(&self, pos: [usize; 2], movement: Movement) -> Vec<[usize; 2]> {
+        let [rows, cols] = self.size();
 
-            if next.cost < dist[next.position[0]][next.position[1]] {
-                heap.push(next);
-                dist[next.position[0]][next.position[1]] = next.cost;
+        movement.offsets().iter()
+            .map(|[di, dj]| [pos[0].wrapping_add_signed(*di), pos[1].wrapping_add_signed(*dj)])
+            .filter(|[i, j]| *i < rows && *j < cols)
+            .collect()
+    }
+
+    /// A* from the top left to the bottom right corner.
+    pub fn find_path(&self, movement: Movement, cost_model: Cost) -> Option<Route> {
+        let [rows, cols] = self.size();
+        let goal = [rows - 1, cols - 1];
+        let heuristic = |p: [usize; 2]| cost_model.lower_bound(self, movement, p, goal);
+
+        let mut dist: Vec<Vec<usize>> = vec![vec![usize::MAX; cols]; rows];
+        let mut came_from: Vec<Vec<Option<[usize; 2]>>> = vec![vec![None; cols]; rows];
+        let mut heap = BinaryHeap::new();
+
+        dist[0][0] = 0;
+        heap.push(State { priority: heuristic([0, 0]), cost: 0, position: [0, 0] });
+
+        while let Some(State { cost, position, .. }) = heap.pop() {
+            if position == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while let Some(p) = came_from[current[0]][current[1]] {
+                    path.push(p);
+                    current = p;
+                }
+                path.reverse();
+
+                return Some(Route { cost, path });
+            }
+
+            if cost > dist[position[0]][position[1]] {
+                continue;
+            }
+
+            for n in self.find_neighbors(position, movement) {
+                let next_cost = cost + cost_model.step(self, position, n);
+
+                if next_cost < dist[n[0]][n[1]] {
+                    dist[n[0]][n[1]] = next_cost;
+                    came_from[n[0]][n[1]] = Some(position);
+                    heap.push(State { priority: next_cost + heuristic(n), cost: next_cost, position: n });
+                }
             }
         }
+
+        None
     }
 
-    None
+    /// The map with only the risk levels on the route, '.' elsewhere.
+    pub fn render_route(&self, route: &Route) -> String {
+        let [rows, cols] = self.size();
+        let mut on_route = vec![vec![false; cols]; rows];
+        route.path.iter().for_each(|p| on_route[p[0]][p[1]] = true);
+
+        let mut text = String::new();
+        for (i, row) in on_route.iter().enumerate() {
+            for (j, on) in row.iter().enumerate() {
+                text.push(match on {
+                    true => char::from_digit(self.risk([i, j]) as u32, 10).unwrap(),
+                    false => '.',
+                });
+            }
+            text.push('\n');
+        }
+
+        text
+    }
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Chiton]...      ");
+impl FromStr for RiskMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cases: Vec<Vec<usize>> = vec![];
+
+        for val in s.lines() {
+            if val.is_empty() {
+                continue;
+            }
 
-    let mut cases: Vec<Vec<usize>> = vec![];
+            let row = val.chars()
+                .map(|x| match x.to_digit(10) {
+                    Some(d) if d > 0 => Ok(d as usize),
+                    _ => Err(format!("Invalid risk level '{}'", x)),
+                })
+                .collect::<Result<Vec<usize>, String>>()?;
 
-    // Load input data
-    for val in contents.lines() {
-        if val.is_empty() {
-            continue;
+            if !cases.is_empty() && row.len() != cases[0].len() {
+                return Err(String::from("Rows have different lengths"));
+            }
+            cases.push(row);
+        }
+
+        if cases.is_empty() {
+            return Err(String::from("Empty map"));
         }
-        cases.push(Vec::from_iter(val.chars().map(|x| x.to_string().parse().unwrap() )));
-    }
 
-    if let Some(v) = find_path(&cases) {
-        print!("{} ", v);
+        Ok(RiskMap { cases, tiles: 1 })
     }
+}
+
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let map = RiskMap::from_str(contents)?;
 
-    let mut cases2: Vec<Vec<usize>> = vec![];
-    for v in &cases {
-        cases2.push(vec![]);
-        for j in 0..5 {
-            let mut new_vec: Vec<usize> = v.iter().map(|x| if (*x + j) >= 10 {((*x + j) % 10) + 1} else {*x + j}).collect();
-            cases2.last_mut().unwrap().append(&mut new_vec);
+    for (tiles, name) in [(1, "2021-15.txt"), (5, "2021-15-tiled.txt")] {
+        let tiled = map.tiled(tiles);
+        if let Some(route) = tiled.find_path(Movement::Orthogonal, Cost::Enter) {
+            fs::write(dir.join(name), tiled.render_route(&route)).map_err(|e| e.to_string())?;
         }
     }
 
-    let len = cases2.len();
-    for j in 1..5 {
-        for i in 0..len {
-            let new_vec: Vec<usize> = cases2[i].iter().map(|x| if (*x + j) >= 10 {((*x + j) % 10) + 1} else {*x + j}).collect();
-            cases2.push(new_vec);
+    // Compare every movement and cost model on the map
+    let mut report = String::new();
+    for movement in Movement::ALL {
+        for cost in Cost::ALL {
+            let result = match map.find_path(movement, cost) {
+                Some(r) => format!("cost {}, {} moves", r.cost, r.path.len() - 1),
+                None => String::from("unreachable"),
+            };
+            report.push_str(&format!("{:?} {:?}: {}\n", movement, cost, result));
         }
     }
 
-    if let Some(v) = find_path(&cases2) {
-        println!("{}", v);
+    fs::write(dir.join("2021-15-variants.txt"), report).map_err(|e| e.to_string())
+}
+
+pub fn run(contents: &str) -> Result<(), String> {
+    print!("[Chiton]...      ");
+
+    let map = RiskMap::from_str(contents)?;
+
+    if let Some(r) = map.find_path(Movement::Orthogonal, Cost::Enter) {
+        print!("{} ", r.cost);
+    }
+
+    if let Some(r) = map.tiled(5).find_path(Movement::Orthogonal, Cost::Enter) {
+        println!("{}", r.cost);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,73 +1,307 @@
-use std::cmp::{max, min};
+use std::cmp::max;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::ops::{Add, Mul};
+use std::path::Path;
+use std::str::FromStr;
+use crate::bigint::BigUint;
+use crate::solver::{self, Implementation, Kind, Mode};
+
+const IMPLEMENTATIONS: [Implementation<Overflow, Value>; 3] = [
+    Implementation { name: "decoded", kind: Kind::Fast, solve: evaluate },
+    Implementation { name: "re-encoded", kind: Kind::Reference, solve: evaluate_round_trip },
+    Implementation { name: "expression", kind: Kind::Reference, solve: evaluate_expression },
+];
+
+const LITERAL_TYPE: u8 = 4;
+
+/// What to do when values of the transmission do not fit in 64 bits.
+#[derive(Copy, Clone)]
+pub enum Overflow {
+    /// Carry on with arbitrary precision
+    Extend,
+    /// Same, with a warning
+    Warn,
+    Error,
+}
+
+/// A packet value, u64 as long as it fits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Small(u64),
+    /// Only for values above u64::MAX
+    Big(BigUint),
+}
+
+impl Value {
+    fn from_big(v: BigUint) -> Value {
+        match v.to_u64() {
+            Some(s) => Value::Small(s),
+            None => Value::Big(v),
+        }
+    }
+
+    fn to_big(&self) -> BigUint {
+        match self {
+            Value::Small(v) => BigUint::from(*v),
+            Value::Big(v) => v.clone(),
+        }
+    }
+
+    fn is_wide(&self) -> bool {
+        matches!(self, Value::Big(_))
+    }
+
+    fn bits(&self) -> usize {
+        match self {
+            Value::Small(v) => (u64::BITS - v.leading_zeros()) as usize,
+            Value::Big(v) => v.bits(),
+        }
+    }
+
+    // Group g of 4 bits, the least significant one being 0
+    fn nibble(&self, g: usize) -> usize {
+        match self {
+            Value::Small(v) => (v.checked_shr(4 * g as u32).unwrap_or(0) & 0xF) as usize,
+            Value::Big(v) => (0..4).filter(|i| v.bit(4 * g + i)).map(|i| 1 << i).sum(),
+        }
+    }
+}
+
+impl Add<&Value> for &Value {
+    type Output = Value;
+
+    fn add(self, other: &Value) -> Value {
+        if let (Value::Small(a), Value::Small(b)) = (self, other) {
+            if let Some(s) = a.checked_add(*b) {
+                return Value::Small(s);
+            }
+        }
+        Value::from_big(&self.to_big() + &other.to_big())
+    }
+}
+
+impl Mul<&Value> for &Value {
+    type Output = Value;
+
+    fn mul(self, other: &Value) -> Value {
+        if let (Value::Small(a), Value::Small(b)) = (self, other) {
+            if let Some(p) = a.checked_mul(*b) {
+                return Value::Small(p);
+            }
+        }
+        Value::from_big(&self.to_big() * &other.to_big())
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Small(a), Value::Small(b)) => a.cmp(b),
+            (Value::Small(_), Value::Big(_)) => Ordering::Less,
+            (Value::Big(_), Value::Small(_)) => Ordering::Greater,
+            (Value::Big(a), Value::Big(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Small(v) => write!(f, "{}", v),
+            Value::Big(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+// Operator of each packet type in the expressions
+const OPERATORS: [(u8, &str); 7] = [(0, "+"), (1, "*"), (2, "min"), (3, "max"), (5, ">"), (6, "<"), (7, "=")];
+
+/// Why a transmission could not be decoded, with the bit where it happened.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    InvalidHexa { bit: usize, found: char },
+    /// The transmission ends in the middle of a field of `needed` bits
+    Truncated { bit: usize, needed: usize },
+    /// Sub-packets of length type 0 do not end where the length says
+    LengthMismatch { bit: usize, declared: usize, used: usize },
+    OperandCount { bit: usize, p_type: u8, count: usize },
+    UnknownType { bit: usize, p_type: u8 },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidHexa { bit, found } => write!(f, "bit {}: invalid hexadecimal digit '{}'", bit, found),
+            DecodeError::Truncated { bit, needed } => write!(f, "bit {}: transmission ends before {} more bits", bit, needed),
+            DecodeError::LengthMismatch { bit, declared, used } => {
+                write!(f, "bit {}: sub-packets use {} bits instead of {}", bit, used, declared)
+            },
+            DecodeError::OperandCount { bit, p_type, count } => {
+                write!(f, "bit {}: operator of type {} with {} operands", bit, p_type, count)
+            },
+            DecodeError::UnknownType { bit, p_type } => write!(f, "bit {}: unknown packet type {}", bit, p_type),
+        }
+    }
+}
+
+impl From<DecodeError> for String {
+    fn from(e: DecodeError) -> String {
+        e.to_string()
+    }
+}
+
+fn read_n_bits_at(hex: &[u8], at: usize, n: usize) -> Result<usize, DecodeError> {
+    if at + n > hex.len() * 8 {
+        return Err(DecodeError::Truncated { bit: at, needed: n });
+    }
 
-fn read_n_bits_at(hex: &Vec<u8>, at: usize, n: usize) -> usize {
     let mut val = 0;
     for bit in at..at+n {
         if ((hex[bit / 8] >> (8 - (bit % 8) - 1)) & 1) == 1 {
             val += 1usize << (n - (bit-at) - 1)
         }
     }
-    val
+    Ok(val)
 }
 
+// Comparisons take exactly two operands, the other operators at least one
+fn valid_operand_count(p_type: u8, count: usize) -> bool {
+    match p_type {
+        5..=7 => count == 2,
+        _ => count > 0,
+    }
+}
+
+/// Bits are appended most significant first, like `read_n_bits_at` reads them.
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: vec![], len: 0 }
+    }
+
+    fn push(&mut self, value: usize, n: usize) {
+        for i in (0..n).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << (7 - self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    fn to_hexa(&self) -> String {
+        self.bytes.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
 struct PacketHeader {
     version: u8,
     p_type: u8
 }
 
 impl PacketHeader {
-    pub fn from_bytes(hex: &Vec<u8>, start_bit: usize) -> PacketHeader {
-        PacketHeader {
-            version: read_n_bits_at(hex, start_bit, 3) as u8,
-            p_type: read_n_bits_at(hex, start_bit + 3, 3) as u8
+    pub fn from_bytes(hex: &[u8], start_bit: usize) -> Result<PacketHeader, DecodeError> {
+        Ok(PacketHeader {
+            version: read_n_bits_at(hex, start_bit, 3)? as u8,
+            p_type: read_n_bits_at(hex, start_bit + 3, 3)? as u8
+        })
+    }
+
+    fn encode(&self, w: &mut BitWriter) -> Result<(), String> {
+        if self.version > 7 || self.p_type > 7 {
+            return Err(format!("Version {} or type {} does not fit in 3 bits", self.version, self.p_type));
         }
+
+        w.push(self.version as usize, 3);
+        w.push(self.p_type as usize, 3);
+        Ok(())
     }
 }
 
+#[derive(Debug, PartialEq)]
 struct LiteralPacket {
     header: PacketHeader,
-    value: usize,
+    value: Value,
     bits: usize
 }
 
 impl LiteralPacket {
     // return the number and true if it is the last one
-    fn get_group_at(hex: &Vec<u8>, bit: usize) -> (usize, bool) {
-        (read_n_bits_at(hex, bit + 1, 4), read_n_bits_at(hex, bit, 1) != 1)
+    fn get_group_at(hex: &[u8], bit: usize) -> Result<(u64, bool), DecodeError> {
+        Ok((read_n_bits_at(hex, bit + 1, 4)? as u64, read_n_bits_at(hex, bit, 1)? != 1))
     }
 
-    pub fn from_bytes(h: PacketHeader, hex: &Vec<u8>, start_bit: usize) -> LiteralPacket {
+    pub fn from_bytes(h: PacketHeader, hex: &[u8], start_bit: usize) -> Result<LiteralPacket, DecodeError> {
         let mut bit = start_bit;
-        let mut values = vec![];
-        let mut value = 0;
-        let mut l = 0;
+        let mut value = Value::Small(0);
 
+        // Groups come most significant first and there is no limit to their number
         loop {
-            let (v, s) = LiteralPacket::get_group_at(hex, bit);
+            let (v, s) = LiteralPacket::get_group_at(hex, bit)?;
             bit += 5;
-            values.push(v);
+            value = &(&value * &Value::Small(16)) + &Value::Small(v);
             if s {
                 break;
             }
         }
 
-        for v in values.iter().rev() {
-            value += v << (4*l);
-            l += 1;
-        }
-
-        LiteralPacket {
+        Ok(LiteralPacket {
             header: h,
             bits: bit - start_bit,
             value
+        })
+    }
+
+    pub fn new(version: u8, value: Value) -> LiteralPacket {
+        let groups = max(1, value.bits().div_ceil(4));
+
+        LiteralPacket {
+            header: PacketHeader { version, p_type: LITERAL_TYPE },
+            value,
+            bits: groups * 5,
         }
     }
 
     pub fn bits_consumed(&self) -> usize {
         self.bits + 6
     }
+
+    // The value is split in groups of 4 bits, each one prefixed with 1 except the last one
+    fn encode(&self, w: &mut BitWriter) -> Result<(), String> {
+        self.header.encode(w)?;
+
+        // A decoded literal can start with zero groups, they are written again so that the size
+        // matches bits_consumed
+        let groups = self.bits / 5;
+        if groups < self.value.bits().div_ceil(4) {
+            return Err(format!("{} groups are too few for the literal {}", groups, self.value));
+        }
+
+        for g in (0..groups).rev() {
+            w.push((g > 0) as usize, 1);
+            w.push(self.value.nibble(g), 4);
+        }
+
+        Ok(())
+    }
 }
 
+#[derive(Debug, PartialEq)]
 struct OperatorPacket {
     header: PacketHeader,
     subs: Vec<PacketType>,
@@ -75,38 +309,55 @@ struct OperatorPacket {
 }
 
 impl OperatorPacket {
-    pub fn from_bytes(h: PacketHeader, hex: &Vec<u8>, start_bit: usize) -> OperatorPacket {
-        let i = read_n_bits_at(hex, start_bit, 1);
+    pub fn from_bytes(h: PacketHeader, hex: &[u8], start_bit: usize) -> Result<OperatorPacket, DecodeError> {
+        let i = read_n_bits_at(hex, start_bit, 1)?;
         let mut subs = vec![];
         let size_bits;
 
         if i == 0 {
-            let mut sub_bits = read_n_bits_at(hex, start_bit+1, 15);
+            let sub_bits = read_n_bits_at(hex, start_bit+1, 15)?;
             size_bits = 16;
-            let mut sub_start_bit = start_bit + size_bits;
+            let mut used = 0;
 
-            while sub_bits > 0 {
+            while used < sub_bits {
                 let mut consumed = 0;
-                subs.push(PacketType::from_hexa(hex, sub_start_bit, &mut consumed));
-                sub_start_bit += consumed;
-                sub_bits -= consumed;
+                subs.push(PacketType::from_hexa(hex, start_bit + size_bits + used, &mut consumed)?);
+                used += consumed;
+            }
+
+            if used != sub_bits {
+                return Err(DecodeError::LengthMismatch { bit: start_bit + 1, declared: sub_bits, used });
             }
         } else {
-            let sub_count = read_n_bits_at(hex, start_bit+1, 11);
+            let sub_count = read_n_bits_at(hex, start_bit+1, 11)?;
             size_bits = 12;
             let mut sub_start_bit = start_bit + size_bits;
 
             for _ in 0..sub_count {
                 let mut consumed = 0;
-                subs.push(PacketType::from_hexa(hex, sub_start_bit, &mut consumed));
+                subs.push(PacketType::from_hexa(hex, sub_start_bit, &mut consumed)?);
                 sub_start_bit += consumed;
             }
         }
 
-        OperatorPacket {
+        // The header is right before
+        if !valid_operand_count(h.p_type, subs.len()) {
+            return Err(DecodeError::OperandCount { bit: start_bit - 6, p_type: h.p_type, count: subs.len() });
+        }
+
+        Ok(OperatorPacket {
             header: h,
             subs,
             size_bits
+        })
+    }
+
+    /// Use length type 1 when possible, its header is shorter.
+    pub fn new(version: u8, p_type: u8, subs: Vec<PacketType>) -> OperatorPacket {
+        OperatorPacket {
+            header: PacketHeader { version, p_type },
+            size_bits: if subs.len() < 1 << 11 { 12 } else { 16 },
+            subs,
         }
     }
 
@@ -121,6 +372,33 @@ impl OperatorPacket {
         self.size_bits + sub_size + 6
     }
 
+    // A decoded packet keeps the length type it was read with. size_bits is 16 for length type 0
+    // (total bits of the sub-packets) and 12 for length type 1 (number of sub-packets).
+    fn encode(&self, w: &mut BitWriter) -> Result<(), String> {
+        self.header.encode(w)?;
+
+        if self.size_bits == 16 {
+            let sub_bits: usize = self.subs.iter().map(|s| s.bits_consumed()).sum();
+            if sub_bits >= 1 << 15 {
+                return Err(format!("Sub-packets use {} bits, more than length type 0 allows", sub_bits));
+            }
+            w.push(0, 1);
+            w.push(sub_bits, 15);
+        } else {
+            if self.subs.len() >= 1 << 11 {
+                return Err(format!("{} sub-packets, more than length type 1 allows", self.subs.len()));
+            }
+            w.push(1, 1);
+            w.push(self.subs.len(), 11);
+        }
+
+        for s in &self.subs {
+            s.encode(w)?;
+        }
+
+        Ok(())
+    }
+
     pub fn count_vers(&self) -> usize {
         let mut v: usize = self.header.version as usize;
         for c in &self.subs {
@@ -132,115 +410,244 @@ impl OperatorPacket {
         v
     }
 
-    pub fn resolve(&self) -> usize {
-        let mut values = vec![];
-        for s in &self.subs {
-            values.push(match s {
-                PacketType::Operator(p) => p.resolve(),
-                PacketType::Literal(p) => p.value as usize,
-            });
-        }
-
-        match self.header.p_type {
-            0 => values.iter().sum(),
-            1 => {
-                let mut ret = 1;
-                values.iter().for_each(|x| ret *= *x);
-                ret
-            },
-            2 => {
-                let mut ret = usize::MAX;
-                values.iter().for_each(|x| ret = min(ret, *x));
-                ret
-            },
-            3 => {
-                let mut ret = 0;
-                values.iter().for_each(|x| ret = max(ret, *x));
-                ret
-            },
-            5 => {
-                if values[0] > values[1] {
-                    1
-                } else {
-                    0
-                }
-            }
-            6 => {
-                if values[0] < values[1] {
-                    1
-                } else {
-                    0
-                }
-            }
-            7 => {
-                if values[0] == values[1] {
-                    1
-                } else {
-                    0
-                }
-            }
-            _ => 0
+    /// Count in `wide` the values that do not fit in 64 bits, this one included.
+    pub fn resolve(&self, wide: &mut usize) -> Value {
+        let values: Vec<Value> = self.subs.iter().map(|s| s.resolve(wide)).collect();
+
+        // Operand counts are checked when decoding or compiling
+        let value = match self.header.p_type {
+            0 => values.iter().fold(Value::Small(0), |acc, v| &acc + v),
+            1 => values.iter().fold(Value::Small(1), |acc, v| &acc * v),
+            2 => values.into_iter().min().unwrap(),
+            3 => values.into_iter().max().unwrap(),
+            5 => Value::Small((values[0] > values[1]) as u64),
+            6 => Value::Small((values[0] < values[1]) as u64),
+            7 => Value::Small((values[0] == values[1]) as u64),
+            t => unreachable!("Packet type {} is rejected when decoding", t),
+        };
+
+        if value.is_wide() {
+            *wide += 1;
         }
+        value
     }
 }
 
+#[derive(Debug, PartialEq)]
 enum PacketType {
     Literal(LiteralPacket),
     Operator(OperatorPacket)
 }
 
 impl PacketType {
-    pub fn from_hexa(bytes: &Vec<u8>, start_bit: usize, size_bits: &mut usize) -> PacketType {
-        let header = PacketHeader::from_bytes(bytes, start_bit);
+    pub fn from_hexa(bytes: &[u8], start_bit: usize, size_bits: &mut usize) -> Result<PacketType, DecodeError> {
+        let header = PacketHeader::from_bytes(bytes, start_bit)?;
         match header.p_type {
-            4 => {
-                let p = LiteralPacket::from_bytes(header, bytes, start_bit + 6);
+            LITERAL_TYPE => {
+                let p = LiteralPacket::from_bytes(header, bytes, start_bit + 6)?;
                 *size_bits = p.bits_consumed();
-                PacketType::Literal(p)
+                Ok(PacketType::Literal(p))
             },
-            _ => {
-                let p = OperatorPacket::from_bytes(header, bytes, start_bit + 6);
+            t if OPERATORS.iter().any(|(o, _)| *o == t) => {
+                let p = OperatorPacket::from_bytes(header, bytes, start_bit + 6)?;
                 *size_bits = p.bits_consumed();
-                PacketType::Operator(p)
+                Ok(PacketType::Operator(p))
             },
+            t => Err(DecodeError::UnknownType { bit: start_bit + 3, p_type: t }),
+        }
+    }
+
+    pub fn version_sum(&self) -> usize {
+        match self {
+            PacketType::Literal(p) => p.header.version as usize,
+            PacketType::Operator(p) => p.count_vers(),
+        }
+    }
+
+    pub fn bits_consumed(&self) -> usize {
+        match self {
+            PacketType::Literal(p) => p.bits_consumed(),
+            PacketType::Operator(p) => p.bits_consumed(),
+        }
+    }
+
+    fn encode(&self, w: &mut BitWriter) -> Result<(), String> {
+        match self {
+            PacketType::Literal(p) => p.encode(w),
+            PacketType::Operator(p) => p.encode(w),
+        }
+    }
+
+    /// The transmission of this packet, padded with 0 to full bytes.
+    pub fn to_hexa(&self) -> Result<String, String> {
+        let mut w = BitWriter::new();
+        self.encode(&mut w)?;
+
+        Ok(w.to_hexa())
+    }
+
+    pub fn resolve(&self, wide: &mut usize) -> Value {
+        match self {
+            PacketType::Literal(p) => {
+                if p.value.is_wide() {
+                    *wide += 1;
+                }
+                p.value.clone()
+            },
+            PacketType::Operator(p) => p.resolve(wide),
         }
     }
 }
 
+/// Expression form of the packet, e.g. `(+ 1 (* 2 3) (max 4 5))`. Versions are left out.
+impl Display for PacketType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PacketType::Literal(p) => write!(f, "{}", p.value),
+            PacketType::Operator(p) => {
+                match OPERATORS.iter().find(|(t, _)| *t == p.header.p_type) {
+                    Some((_, op)) => write!(f, "({}", op)?,
+                    None => write!(f, "(type{}", p.header.p_type)?,
+                }
+                for s in &p.subs {
+                    write!(f, " {}", s)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[BITS]...        ");
+fn tokenize(expr: &str) -> Vec<String> {
+    expr.replace('(', " ( ").replace(')', " ) ").split_whitespace().map(String::from).collect()
+}
+
+fn compile_tokens(tokens: &[String], pos: &mut usize) -> Result<PacketType, String> {
+    let token = match tokens.get(*pos) {
+        Some(t) => t,
+        None => return Err(String::from("Unexpected end of expression")),
+    };
+    *pos += 1;
+
+    if token != "(" {
+        let value = Value::from_big(BigUint::from_str(token)?);
+        return Ok(PacketType::Literal(LiteralPacket::new(0, value)));
+    }
+
+    let start = *pos;
+    let p_type = match tokens.get(*pos).and_then(|op| OPERATORS.iter().find(|(_, o)| o == op)) {
+        Some((t, _)) => *t,
+        None => return Err(format!("Unknown operator '{}'", tokens.get(*pos).unwrap_or(&String::new()))),
+    };
+    *pos += 1;
+
+    let mut subs = vec![];
+    while tokens.get(*pos).map(|t| t.as_str()) != Some(")") {
+        subs.push(compile_tokens(tokens, pos)?);
+    }
+    *pos += 1;
+
+    if !valid_operand_count(p_type, subs.len()) {
+        return Err(format!("Operator '{}' with {} operands", tokens[start], subs.len()));
+    }
+
+    Ok(PacketType::Operator(OperatorPacket::new(0, p_type, subs)))
+}
+
+/// Build the packets of an expression written like the `Display` output.
+fn compile(expr: &str) -> Result<PacketType, String> {
+    let tokens = tokenize(expr);
+    let mut pos = 0;
+    let packet = compile_tokens(&tokens, &mut pos)?;
+
+    if pos != tokens.len() {
+        return Err(format!("Unexpected '{}' after the expression", tokens[pos]));
+    }
 
-    let mut cases: Vec<PacketType> = vec![];
-    let mut vers = 0;
-    let mut last_pos= 0;
+    Ok(packet)
+}
 
-    let val = contents.trim();
+// Transmissions are whole bytes, a lone last digit means one is cut short
+fn parse_hexa(val: &str) -> Result<Vec<u8>, DecodeError> {
     let mut bytes = vec![];
-    for i in (0..val.len()).step_by(2) {
-        bytes.push(u8::from_str_radix(&val[i..i+2], 16).unwrap());
+    for (i, c) in val.chars().enumerate() {
+        let digit = match c.to_digit(16) {
+            Some(d) => d as u8,
+            None => return Err(DecodeError::InvalidHexa { bit: i * 4, found: c }),
+        };
+
+        if i % 2 == 0 {
+            bytes.push(digit << 4);
+        } else {
+            *bytes.last_mut().unwrap() |= digit;
+        }
     }
 
-    while last_pos < bytes.len() {
-        let mut consumed= 0;
+    if val.len() % 2 == 1 {
+        return Err(DecodeError::Truncated { bit: val.len() * 4, needed: 4 });
+    }
+
+    Ok(bytes)
+}
+
+/// The outermost packet, the bits left after it are padding.
+fn decode(contents: &str) -> Result<PacketType, DecodeError> {
+    let mut consumed = 0;
+
+    PacketType::from_hexa(&parse_hexa(contents.trim())?, 0, &mut consumed)
+}
+
+fn resolve_with(packet: &PacketType, overflow: Overflow) -> Result<Value, String> {
+    let mut wide = 0;
+    let value = packet.resolve(&mut wide);
 
-        cases.push(PacketType::from_hexa(&bytes, last_pos, &mut consumed));
-        if consumed == 0 {
-            println!("No packet was consumed, Leaving");
-            break;
+    if wide > 0 {
+        match overflow {
+            Overflow::Extend => {},
+            Overflow::Warn => eprintln!("Warning: {} values of the transmission exceed 64 bits", wide),
+            Overflow::Error => return Err(format!("{} values of the transmission exceed 64 bits", wide)),
         }
+    }
 
-        last_pos += consumed;
-        vers += match cases.last().unwrap() {
-            PacketType::Literal(p) => p.header.version as usize,
-            PacketType::Operator(p) => p.count_vers(),
-        };
+    Ok(value)
+}
+
+fn evaluate(contents: &str, overflow: &Overflow) -> Result<Value, String> {
+    resolve_with(&decode(contents)?, *overflow)
+}
+
+// Encode the decoded packet again: decoding that must give back the same packet
+fn evaluate_round_trip(contents: &str, overflow: &Overflow) -> Result<Value, String> {
+    let packet = decode(contents)?;
+    let round_trip = decode(&packet.to_hexa()?)?;
+
+    if round_trip != packet {
+        return Err(String::from("Packet changed after encoding and decoding it"));
     }
 
-    let r = match &cases[0] {
-        PacketType::Literal(p) => p.value as usize,
-        PacketType::Operator(p) => p.resolve(),
-    };
+    resolve_with(&round_trip, *overflow)
+}
+
+// Print the packet, then compile the expression back and encode it
+fn evaluate_expression(contents: &str, overflow: &Overflow) -> Result<Value, String> {
+    let expr = decode(contents)?.to_string();
+
+    resolve_with(&decode(&compile(&expr)?.to_hexa()?)?, *overflow)
+}
+
+/// Write the transmission as an expression, and its value.
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let packet = decode(contents)?;
+    let value = resolve_with(&packet, Overflow::Extend)?;
+
+    fs::write(dir.join("2021-16.txt"), format!("{}\n= {}\n", packet, value)).map_err(|e| e.to_string())
+}
+
+pub fn run(contents: &str, mode: Mode, overflow: Overflow) -> Result<(), String> {
+    print!("[BITS]...        ");
+
+    let vers = decode(contents)?.version_sum();
+    let r = solver::solve(&IMPLEMENTATIONS, mode, contents, &overflow)?;
 
     println!("{} {}", vers, r);
 
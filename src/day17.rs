@@ -1,56 +1,280 @@
-use std::cmp::max;
+use std::cmp::{max, min};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
 
+/// Target area, bounds included. It can be anywhere around the launcher, which is at 0,0.
 struct Target {
     x: [isize; 2],
     y: [isize; 2]
 }
 
+/// A launch velocity that ends in the target.
+pub struct Hit {
+    pub velocity: [isize; 2],
+    /// Highest y reached, the launcher included
+    pub apex: isize,
+    /// First step with the probe in the target
+    pub step: usize,
+}
+
+/// Positions of the probe after each step, until it is in the target or can no longer get there.
+pub struct Shot {
+    pub path: Vec<[isize; 2]>,
+    pub hit: Option<Hit>,
+}
+
+// Distance travelled before the drag stops the probe
+fn triangle(v: isize) -> isize {
+    v * (v + 1) / 2
+}
+
+// Smallest speed that travels at least d before stopping
+fn triangle_root(d: isize) -> isize {
+    if d <= 0 {
+        return 0;
+    }
+
+    let mut v = (((8 * d + 1) as f64).sqrt() as isize - 1) / 2;
+    while triangle(v) < d {
+        v += 1;
+    }
+    while v > 0 && triangle(v - 1) >= d {
+        v -= 1;
+    }
+    v
+}
+
 impl Target {
     pub fn contains(&self, p: [isize; 2]) -> bool {
         p[0] >= self.x[0] && p[0] <= self.x[1] &&
             p[1] >= self.y[0] && p[1] <= self.y[1]
     }
 
-    pub fn try_hit(&self, v: [isize; 2]) -> bool {
+    /// Follow the probe until it cannot reach the target anymore. The launch point itself does
+    /// not count as a hit, the probe has to be in the target after a step.
+    pub fn try_hit(&self, v: [isize; 2]) -> Shot {
         let mut pos = [0, 0];
         let mut delta_v = v;
-        while pos[0] <= self.x[1] && pos[1] >= self.y[0] {
-            if self.contains(pos) {
-                return true;
-            }
+        let mut path = vec![];
 
+        loop {
             //Update the position and speed
             pos[0] += delta_v[0];
             pos[1] += delta_v[1];
+            path.push(pos);
 
-            delta_v[0] = max(0, delta_v[0] - 1);
+            delta_v[0] -= delta_v[0].signum();
             delta_v[1] -= 1;
+
+            if self.contains(pos) {
+                let hit = Hit { velocity: v, apex: triangle(max(v[1], 0)), step: path.len() };
+                return Shot { path, hit: Some(hit) };
+            }
+
+            let falling_below = pos[1] < self.y[0] && delta_v[1] < 0;
+            let passed = (pos[0] > self.x[1] && delta_v[0] >= 0) || (pos[0] < self.x[0] && delta_v[0] <= 0);
+            if falling_below || passed {
+                return Shot { path, hit: None };
+            }
+        }
+    }
+
+    /// Horizontal velocities that can end in the target: the drag must not stop the probe
+    /// before it, and the first step must not go past it.
+    fn vx_range(&self) -> [isize; 2] {
+        if self.x[0] > 0 {
+            [triangle_root(self.x[0]), self.x[1]]
+        } else if self.x[1] < 0 {
+            [self.x[0], -triangle_root(-self.x[1])]
+        } else {
+            [self.x[0], self.x[1]]
+        }
+    }
+
+    /// Vertical velocities that can end in the target, or an error when there are infinitely
+    /// many of them.
+    fn vy_range(&self) -> Result<[isize; 2], String> {
+        // The first step is the highest when going down, and going up has to reach the target
+        let low = match self.y[0] > 0 {
+            true => triangle_root(self.y[0]),
+            false => self.y[0],
+        };
+
+        if self.y[0] > 0 {
+            // Going higher jumps over the target on the way up and on the way down
+            return Ok([low, self.y[1]]);
+        }
+
+        if self.y[1] < 0 {
+            // The probe comes back to y=0 with a speed of -(vy+1), which must not skip the target
+            return Ok([low, -self.y[0] - 1]);
+        }
+
+        // The target contains y=0, which the probe crosses whatever vy is. If the drag stops the
+        // probe within the target, every high enough shot falls in it.
+        let [vx_low, vx_high] = self.vx_range();
+        if (vx_low..=vx_high).any(|vx| (self.x[0]..=self.x[1]).contains(&(vx.signum() * triangle(vx.abs())))) {
+            return Err(String::from("Infinitely many velocities hit the target"));
         }
 
-        false
+        // Otherwise the probe is only over the target while it moves horizontally, for at most
+        // `steps` steps, and cannot climb past y1 in that time
+        let steps = max(self.x[0].abs(), self.x[1].abs());
+        Ok([low, max(-self.y[0] - 1, self.y[1] + steps)])
     }
-}
 
-pub fn run() -> Result<(), String> {
-    print!("[Shot]...        ");
-    let target = Target {
-        x: [94, 151],
-        y: [-156, -103],
-    };
-
-    let mut max_height = 0;
-    let mut contact_count = 0;
-
-    for x in 1..=target.x[1] {
-        for y in target.y[0]..=-target.y[0] {
-            if target.try_hit([x, y]) {
-                max_height = max((y * (1 + y))/2, max_height);
-                contact_count += 1;
+    /// Every velocity that ends in the target.
+    pub fn hits(&self) -> Result<Vec<Hit>, String> {
+        let [vx_low, vx_high] = self.vx_range();
+        let [vy_low, vy_high] = self.vy_range()?;
+        let mut hits = vec![];
+
+        for vx in vx_low..=vx_high {
+            for vy in vy_low..=vy_high {
+                if let Some(h) = self.try_hit([vx, vy]).hit {
+                    hits.push(h);
+                }
+            }
+        }
+
+        Ok(hits)
+    }
+
+    // [[x min, x max], [y min, y max]] of the launcher, the shot and the target
+    fn extent(&self, shot: &Shot) -> [[isize; 2]; 2] {
+        let mut e = [[min(0, self.x[0]), max(0, self.x[1])], [min(0, self.y[0]), max(0, self.y[1])]];
+
+        for p in &shot.path {
+            for axis in 0..2 {
+                e[axis] = [min(e[axis][0], p[axis]), max(e[axis][1], p[axis])];
+            }
+        }
+
+        e
+    }
+
+    /// Draw the shot like the puzzle: S for the launcher, # for the probe and T for the target,
+    /// with y going up.
+    pub fn render_ascii(&self, shot: &Shot, max_size: usize) -> Result<String, String> {
+        let [[x_min, x_max], [y_min, y_max]] = self.extent(shot);
+        let [width, height] = [(x_max - x_min + 1) as usize, (y_max - y_min + 1) as usize];
+
+        if width > max_size || height > max_size {
+            return Err(format!("Shot is {}x{}, too large for ASCII output (max {})", width, height, max_size));
+        }
+
+        let mut text = String::new();
+        for y in (y_min..=y_max).rev() {
+            for x in x_min..=x_max {
+                text.push(match [x, y] {
+                    [0, 0] => 'S',
+                    p if shot.path.contains(&p) => '#',
+                    p if self.contains(p) => 'T',
+                    _ => '.',
+                });
             }
+            text.push('\n');
+        }
+
+        Ok(text)
+    }
+
+    pub fn render_svg(&self, shot: &Shot, scale: usize) -> String {
+        let [[x_min, x_max], [y_min, y_max]] = self.extent(shot);
+        let scale = scale as isize;
+        // Centre of each position, y going up
+        let to_svg = |p: [isize; 2]| [(p[0] - x_min) * scale + scale / 2, (y_max - p[1]) * scale + scale / 2];
+
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+                              (x_max - x_min + 1) * scale, (y_max - y_min + 1) * scale);
+        svg.push_str("  <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+        svg.push_str(&format!("  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"lightblue\"/>\n",
+                              (self.x[0] - x_min) * scale, (y_max - self.y[1]) * scale,
+                              (self.x[1] - self.x[0] + 1) * scale, (self.y[1] - self.y[0] + 1) * scale));
+
+        let points: Vec<String> = [[0, 0]].iter().chain(&shot.path)
+            .map(|p| { let [x, y] = to_svg(*p); format!("{},{}", x, y) })
+            .collect();
+        let colour = match shot.hit {
+            Some(_) => "black",
+            None => "red",
+        };
+        svg.push_str(&format!("  <polyline points=\"{}\" fill=\"none\" stroke=\"{}\"/>\n", points.join(" "), colour));
+
+        let [sx, sy] = to_svg([0, 0]);
+        svg.push_str(&format!("  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"green\"/>\n", sx, sy, max(scale, 2)));
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid target '{}'", s);
+        let ranges = s.trim().strip_prefix("target area: ").ok_or_else(invalid)?;
+        let mut bounds = [[0; 2]; 2];
+
+        if ranges.split(", ").count() != 2 {
+            return Err(invalid());
         }
+
+        for (i, (r, axis)) in ranges.split(", ").zip(["x=", "y="]).enumerate() {
+            let (a, b) = r.strip_prefix(axis).and_then(|r| r.split_once("..")).ok_or_else(invalid)?;
+            let a: isize = a.parse().map_err(|_| invalid())?;
+            let b: isize = b.parse().map_err(|_| invalid())?;
+            bounds[i] = [min(a, b), max(a, b)];
+        }
+
+        Ok(Target { x: bounds[0], y: bounds[1] })
+    }
+}
+
+/// Write every velocity that hits the target, with its apex and the step it gets in. Then draw the
+/// most direct shot, the highest one and the near miss just above it.
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let target = Target::from_str(contents)?;
+    let hits = target.hits()?;
+    let mut report = String::new();
+
+    for h in &hits {
+        report.push_str(&format!("{},{}: apex {}, step {}\n", h.velocity[0], h.velocity[1], h.apex, h.step));
+    }
+
+    fs::write(dir.join("2021-17.txt"), report).map_err(|e| e.to_string())?;
+
+    // Drawn as an SVG when too large for text
+    if let Some(direct) = hits.iter().min_by_key(|h| h.step) {
+        let shot = target.try_hit(direct.velocity);
+        match target.render_ascii(&shot, 200) {
+            Ok(text) => fs::write(dir.join("2021-17-direct.txt"), text),
+            Err(_) => fs::write(dir.join("2021-17-direct.svg"), target.render_svg(&shot, 1)),
+        }.map_err(|e| e.to_string())?;
     }
 
-    println!("{} {}", max_height, contact_count);
+    if let Some(highest) = hits.iter().max_by_key(|h| h.apex) {
+        let [vx, vy] = highest.velocity;
+        fs::write(dir.join("2021-17-highest.svg"), target.render_svg(&target.try_hit([vx, vy]), 1))
+            .map_err(|e| e.to_string())?;
+        fs::write(dir.join("2021-17-miss.svg"), target.render_svg(&target.try_hit([vx, vy + 1]), 1))
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+pub fn run(contents: &str) -> Result<(), String> {
+    print!("[Shot]...        ");
+
+    let hits = Target::from_str(contents)?.hits()?;
+
+    match hits.iter().map(|h| h.apex).max() {
+        Some(apex) => println!("{} {}", apex, hits.len()),
+        None => println!("missed"),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,3 +1,7 @@
+use std::cmp::{max, min};
+use std::fs;
+use std::path::Path;
+
 fn parse_point(text: &str) -> [usize; 2]
 {
     let vals: Vec<&str> = text.split(',').collect();
@@ -5,7 +9,7 @@ fn parse_point(text: &str) -> [usize; 2]
     [vals[0].parse().unwrap(), vals[1].parse().unwrap()]
 }
 
-fn add_points_from_line(line: &str, list: &mut Vec<[u8; 1000]>, intersections: &mut usize)
+fn add_points_from_line(line: &str, list: &mut [[u8; 1000]], intersections: &mut usize)
 {
     let points: Vec<&str> = line.split("->").collect();
     let p1 = parse_point(points[0].trim());
@@ -65,10 +69,8 @@ fn add_points_from_line(line: &str, list: &mut Vec<[u8; 1000]>, intersections: &
     }
 }
 
-pub fn run(contents: &str) -> Result<(), String>
+fn build_map(contents: &str) -> (Vec<[u8; 1000]>, usize)
 {
-    print!("[Vents]...       ");
-
     let mut points: Vec<[u8; 1000]> = vec![[0; 1000]; 1000];
     let mut intersections: usize = 0;
 
@@ -80,7 +82,124 @@ pub fn run(contents: &str) -> Result<(), String>
         add_points_from_line(line, &mut points, &mut intersections);
     }
 
+    (points, intersections)
+}
+
+/// Size of the smallest [width, height] area starting at 0,0 that holds every vent.
+fn map_extent(list: &[[u8; 1000]]) -> [usize; 2]
+{
+    let mut extent = [0, 0];
+
+    for (x, column) in list.iter().enumerate() {
+        for (y, cell) in column.iter().enumerate() {
+            if *cell > 0 {
+                extent[0] = max(extent[0], x + 1);
+                extent[1] = max(extent[1], y + 1);
+            }
+        }
+    }
+
+    extent
+}
+
+fn max_overlap(list: &[[u8; 1000]]) -> u8
+{
+    list.iter().flat_map(|c| c.iter()).copied().max().unwrap_or(0)
+}
+
+/// Map an overlap count to a black -> red -> yellow -> white heat scale.
+fn heat_colour(count: u8, max_count: u8) -> [u8; 3]
+{
+    if count == 0 || max_count == 0 {
+        return [0, 0, 0];
+    }
+
+    // Spread the non-empty counts over 3 * 255 intensity levels
+    let level = (count as usize * 765) / max_count as usize;
+
+    [min(level, 255) as u8,
+     min(level.saturating_sub(255), 255) as u8,
+     min(level.saturating_sub(510), 255) as u8]
+}
+
+/// Binary PGM (P5) where the grey level is proportional to the overlap count.
+pub fn render_pgm(list: &[[u8; 1000]]) -> Vec<u8>
+{
+    let [width, height] = map_extent(list);
+    let max_count = max(max_overlap(list), 1);
+    let mut image = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+
+    for y in 0..height {
+        for column in &list[..width] {
+            image.push(((column[y] as usize * 255) / max_count as usize) as u8);
+        }
+    }
+
+    image
+}
+
+/// Binary PPM (P6) heat-map of the overlap counts.
+pub fn render_ppm(list: &[[u8; 1000]]) -> Vec<u8>
+{
+    let [width, height] = map_extent(list);
+    let max_count = max_overlap(list);
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+
+    for y in 0..height {
+        for column in &list[..width] {
+            image.extend_from_slice(&heat_colour(column[y], max_count));
+        }
+    }
+
+    image
+}
+
+/// Same layout as the puzzle text: '.' for no vent, the count otherwise.
+/// Only meant for small inputs, counts above 9 are shown as '+'.
+pub fn render_ascii(list: &[[u8; 1000]], max_size: usize) -> Result<String, String>
+{
+    let [width, height] = map_extent(list);
+
+    if width > max_size || height > max_size {
+        return Err(format!("Map is {}x{}, too large for ASCII output (max {})", width, height, max_size));
+    }
+
+    let mut text = String::new();
+    for y in 0..height {
+        for column in &list[..width] {
+            text.push(match column[y] {
+                0 => '.',
+                c @ 1..=9 => (b'0' + c) as char,
+                _ => '+',
+            });
+        }
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+pub fn export(contents: &str, dir: &Path) -> Result<(), String>
+{
+    let (points, _) = build_map(contents);
+
+    fs::write(dir.join("2021-05.pgm"), render_pgm(&points)).map_err(|e| e.to_string())?;
+    fs::write(dir.join("2021-05.ppm"), render_ppm(&points)).map_err(|e| e.to_string())?;
+
+    if let Ok(text) = render_ascii(&points, 80) {
+        fs::write(dir.join("2021-05.txt"), text).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+pub fn run(contents: &str) -> Result<(), String>
+{
+    print!("[Vents]...       ");
+
+    let (_, intersections) = build_map(contents);
+
     println!("{}", intersections);
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,19 +1,67 @@
-
 /*
-This contains 2 implementations.
+This contains 3 implementations.
 The first one creates a Fish object for each fish. It works for 80 days but is not scalable for
-256 (memory and CPU wise). The second implementation just keeps a number of fish at each stage and
-update it for each day.
+256 (memory and CPU wise), so it is only kept as a reference to cross-check the others.
+The second implementation just keeps a number of fish at each stage and update it for each day.
+The third one writes a day as a matrix multiplication on those stages, so the count after n days is
+M^n applied to the initial stages. Exponentiation by squaring answers in O(log n) steps, either
+modulo a given prime or exactly with big integers.
 */
 
+use std::fs;
+use std::path::Path;
+use crate::bigint::BigUint;
+use crate::matrix;
+use crate::solver::{self, Implementation, Kind, Mode};
+
+/// Ages that drive the lifecycle: a fish at age 0 goes back to `reset_age` and spawns a fish at
+/// `newborn_age`.
+#[derive(Copy, Clone)]
+pub struct Lifecycle {
+    pub reset_age: usize,
+    pub newborn_age: usize,
+}
+
+impl Lifecycle {
+    pub const STANDARD: Lifecycle = Lifecycle { reset_age: 6, newborn_age: 8 };
+
+    fn stages(&self) -> usize {
+        self.newborn_age + 1
+    }
+
+    // m[to][from] is how many fish at age `to` one fish at age `from` becomes after a day
+    fn transition_matrix(&self) -> Vec<Vec<u64>> {
+        let mut m = vec![vec![0; self.stages()]; self.stages()];
+
+        for age in 1..self.stages() {
+            m[age - 1][age] = 1;
+        }
+        m[self.reset_age][0] += 1;
+        m[self.newborn_age][0] += 1;
+
+        m
+    }
+}
+
+pub struct Simulation {
+    pub lifecycle: Lifecycle,
+    pub days: u64,
+}
+
+const IMPLEMENTATIONS: [Implementation<Simulation, u64>; 3] = [
+    Implementation { name: "fish objects", kind: Kind::Reference, solve: count_fish_objects },
+    Implementation { name: "stages", kind: Kind::Fast, solve: count_fish_stages },
+    Implementation { name: "matrix power", kind: Kind::Fast, solve: count_fish_matrix },
+];
+
 struct Fish {
     age: usize
 }
 
 impl Fish {
-    pub fn new_born() -> Fish
+    pub fn new_born(lifecycle: &Lifecycle) -> Fish
     {
-        Fish::from_age(8)
+        Fish::from_age(lifecycle.newborn_age)
     }
 
     pub fn from_age(age: usize) -> Fish
@@ -23,11 +71,11 @@ impl Fish {
         }
     }
 
-    pub fn new_day(&mut self) -> Option<Fish>
+    pub fn new_day(&mut self, lifecycle: &Lifecycle) -> Option<Fish>
     {
         if self.age == 0 {
-            self.age = 6;
-            return Some(Fish::new_born());
+            self.age = lifecycle.reset_age;
+            return Some(Fish::new_born(lifecycle));
         }
 
         self.age -= 1;
@@ -36,81 +84,135 @@ impl Fish {
     }
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Fish]...        ");
+fn load_ages(contents: &str, lifecycle: &Lifecycle) -> Result<Vec<usize>, String> {
+    let mut ages = vec![];
 
-    let mut list: Vec<Fish> = vec![];
+    if lifecycle.reset_age > lifecycle.newborn_age {
+        return Err(format!("Reset age {} is older than the newborn age {}",
+                           lifecycle.reset_age, lifecycle.newborn_age));
+    }
 
-    // Load input data
     for val in contents.split(',') {
-        if val.is_empty() {
+        if val.trim().is_empty() {
             continue;
         }
 
-        let val: usize = val.trim().parse().unwrap();
+        let age: usize = val.trim().parse().map_err(|_| format!("Invalid age '{}'", val.trim()))?;
+        if age > lifecycle.newborn_age {
+            return Err(format!("Age {} is older than the newborn age {}", age, lifecycle.newborn_age));
+        }
 
-        list.push(Fish::from_age(val));
+        ages.push(age);
     }
 
-    for _day in 0..80 {
+    Ok(ages)
+}
+
+fn load_stages(contents: &str, lifecycle: &Lifecycle) -> Result<Vec<u64>, String> {
+    let mut fishes = vec![0; lifecycle.stages()];
+
+    for age in load_ages(contents, lifecycle)? {
+        fishes[age] += 1;
+    }
+
+    Ok(fishes)
+}
+
+fn count_fish_objects(contents: &str, sim: &Simulation) -> Result<u64, String> {
+    let mut list: Vec<Fish> = load_ages(contents, &sim.lifecycle)?
+        .into_iter()
+        .map(Fish::from_age)
+        .collect();
+
+    for _day in 0..sim.days {
         let fish_count = list.len();
 
         for i in 0..fish_count {
-            match list[i].new_day() {
+            match list[i].new_day(&sim.lifecycle) {
                 None => continue,
                 Some(f) => list.push(f),
             }
         }
     }
 
-    println!("Day 80: {}", list.len());
-
-    Ok(())
+    Ok(list.len() as u64)
 }
 
-fn new_day(fishes: &[u64; 9]) -> [u64; 9]
+fn new_day(fishes: &[u64], lifecycle: &Lifecycle) -> Vec<u64>
 {
-    let mut ret = [0; 9];
-
-    ret[8] = fishes[0];
-    ret[7] = fishes[8];
-    ret[6] = fishes[7] + fishes[0];
-    ret[5] = fishes[6];
-    ret[4] = fishes[5];
-    ret[3] = fishes[4];
-    ret[2] = fishes[3];
-    ret[1] = fishes[2];
-    ret[0] = fishes[1];
+    let mut ret = vec![0; fishes.len()];
+
+    ret[..fishes.len() - 1].copy_from_slice(&fishes[1..]);
+    ret[lifecycle.reset_age] += fishes[0];
+    ret[lifecycle.newborn_age] += fishes[0];
 
     ret
 }
 
-pub fn run2(contents: &str) -> Result<(), String> {
-    print!("                 ");
-
-    let mut fishes = [0; 9];
+fn count_fish_stages(contents: &str, sim: &Simulation) -> Result<u64, String> {
+    let mut fishes = load_stages(contents, &sim.lifecycle)?;
 
-    // Load input data
-    for val in contents.split(',') {
-        if val.is_empty() {
-            continue;
-        }
+    for _day in 0..sim.days {
+        fishes = new_day(&fishes, &sim.lifecycle);
+    }
 
-        let val: usize = val.trim().parse().unwrap();
+    Ok(fishes.iter().sum())
+}
 
-        fishes[val] += 1;
+/// Number of fish after `days` days, modulo `modulus`, in O(log days) matrix products.
+pub fn count_fish_mod(contents: &str, lifecycle: &Lifecycle, days: u64, modulus: u64) -> Result<u64, String> {
+    if modulus < 2 {
+        return Err(format!("Invalid modulus {}", modulus));
     }
 
-    for _day in 0..256 {
-        fishes = new_day(&fishes);
-    }
+    let fishes = load_stages(contents, lifecycle)?;
+    let p = matrix::pow_mod(&lifecycle.transition_matrix(), days, modulus);
+
+    Ok(matrix::apply_mod(&p, &fishes, modulus).iter()
+        .fold(0, |acc, c| ((acc as u128 + *c as u128) % modulus as u128) as u64))
+}
+
+/// Exact number of fish after `days` days, in O(log days) big integer matrix products.
+pub fn count_fish_exact(contents: &str, lifecycle: &Lifecycle, days: u64) -> Result<BigUint, String> {
+    let fishes = load_stages(contents, lifecycle)?;
+    let p = matrix::pow_big(&lifecycle.transition_matrix(), days);
 
-    let mut sum = 0;
-    for i in 0..9 {
-        sum += fishes[i];
+    let mut sum = BigUint::zero();
+    for count in matrix::apply_big(&p, &fishes) {
+        sum += &count;
     }
 
-    println!("Day 256: {}", sum);
+    Ok(sum)
+}
+
+fn count_fish_matrix(contents: &str, sim: &Simulation) -> Result<u64, String> {
+    let count = count_fish_exact(contents, &sim.lifecycle, sim.days)?;
+
+    count.to_u64().ok_or(format!("{} fish do not fit in 64 bits", count))
+}
+
+/// Write the number of fish after far more days than the puzzle asks.
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let lifecycle = Lifecycle::STANDARD;
+    let report = format!("Day 1000: {}\nDay 10^12 (mod 1000000007): {}\n",
+                         count_fish_exact(contents, &lifecycle, 1000)?,
+                         count_fish_mod(contents, &lifecycle, 1_000_000_000_000, 1_000_000_007)?);
+
+    fs::write(dir.join("2021-06.txt"), report).map_err(|e| e.to_string())
+}
+
+pub fn run(contents: &str, mode: Mode) -> Result<(), String> {
+    print!("[Fish]...        ");
+
+    let lifecycle = Lifecycle::STANDARD;
+
+    // The reference implementation would need over 10^12 fish objects for 256 days, so only the
+    // 80 days answer is cross-checked.
+    let day80 = solver::solve(&IMPLEMENTATIONS, mode, contents, &Simulation { lifecycle, days: 80 })?;
+    let day256 = solver::solve(&IMPLEMENTATIONS, Mode::Normal, contents, &Simulation { lifecycle, days: 256 })?;
+
+    println!("Day 80: {}", day80);
+    println!("                 Day 256: {}", day256);
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,68 +1,116 @@
-fn align_crabs_to(crabs: &Vec<usize>, pos: usize) -> usize
+/// How much fuel a crab engine burns to move a number of steps.
+///
+/// The total fuel is a convex function of the target position as long as the per crab cost is
+/// convex in the number of steps, which lets `best_position` use a ternary search. Engines with a
+/// closed form optimum override it.
+pub trait FuelCost {
+    fn fuel_to_move(&self, steps: usize) -> usize;
+
+    /// `crabs` must not be empty, `align_crabs` checks it.
+    fn best_position(&self, crabs: &[usize]) -> usize {
+        let mut lo = *crabs.iter().min().unwrap_or(&0);
+        let mut hi = *crabs.iter().max().unwrap_or(&0);
+
+        while hi - lo > 2 {
+            let m1 = lo + (hi - lo) / 3;
+            let m2 = hi - (hi - lo) / 3;
+
+            if align_crabs_to(crabs, m1, self) < align_crabs_to(crabs, m2, self) {
+                hi = m2 - 1;
+            } else {
+                lo = m1 + 1;
+            }
+        }
+
+        (lo..=hi).min_by_key(|p| align_crabs_to(crabs, *p, self)).unwrap()
+    }
+}
+
+/// Part 1: every step costs 1 fuel.
+pub struct ConstantCost;
+
+impl FuelCost for ConstantCost {
+    fn fuel_to_move(&self, steps: usize) -> usize {
+        steps
+    }
+
+    // The sum of distances is minimal at the median
+    fn best_position(&self, crabs: &[usize]) -> usize {
+        let mut sorted = crabs.to_vec();
+        sorted.sort_unstable();
+
+        sorted[sorted.len() / 2]
+    }
+}
+
+/// Part 2: the n-th step costs n fuel.
+pub struct TriangularCost;
+
+impl FuelCost for TriangularCost {
+    fn fuel_to_move(&self, steps: usize) -> usize
+    {
+        // The sum of the N consecutive numbers x..x+N is
+        //   (N / 2) * (x + x + N)
+        // In this case we want 1..steps, which is
+        //   (steps / 2) * (1 + steps)
+        // To avoid integer division issues, it can be simplified to:
+        //   (steps * (1 + steps))/2
+
+        (steps * (1 + steps))/2
+    }
+
+    // The cost is (d² + d)/2 per crab, so the optimum is within 1/2 of the mean
+    fn best_position(&self, crabs: &[usize]) -> usize {
+        let mean = crabs.iter().sum::<usize>() / crabs.len();
+
+        (mean.saturating_sub(1)..=mean + 1).min_by_key(|p| align_crabs_to(crabs, *p, self)).unwrap()
+    }
+}
+
+fn align_crabs_to<C: FuelCost + ?Sized>(crabs: &[usize], pos: usize, cost: &C) -> usize
 {
     let mut fuel: usize = 0;
     for crab in crabs {
-        let f = *crab as isize - pos as isize;
-        fuel += fuel_to_move(f.abs() as usize);
+        fuel += cost.fuel_to_move(crab.abs_diff(pos));
     }
 
     fuel
 }
 
-fn fuel_to_move(steps: usize) -> usize
-{
-    // The sum of the N consecutive numbers x..x+N is
-    //   (N / 2) * (x + x + N)
-    // In this case we want 1..steps, which is
-    //   (steps / 2) * (1 + steps)
-    // To avoid integer division issues, it can be simplified to:
-    //   (steps * (1 + steps))/2
-
-    (steps * (1 + steps))/2
-}
+/// Return the best position and the fuel needed to align every crab on it, None without crabs.
+pub fn align_crabs(crabs: &[usize], cost: &dyn FuelCost) -> Option<[usize; 2]> {
+    if crabs.is_empty() {
+        return None;
+    }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Crabs]...       ");
+    let pos = cost.best_position(crabs);
+
+    Some([pos, align_crabs_to(crabs, pos, cost)])
+}
 
+fn load_crabs(contents: &str) -> Result<Vec<usize>, String> {
     let mut crabs = vec![];
-    let min: usize = 0; //Because there is a 0 in the input (that's a cheat)
-    let mut max: usize = 0;
 
-    // Load input data
     for val in contents.split(',') {
-        if val.is_empty() {
+        if val.trim().is_empty() {
             continue;
         }
 
-        let val: usize = val.trim().parse().unwrap();
-
-        if val > max {
-            max = val;
-        }
-
-        crabs.push(val);
+        crabs.push(val.trim().parse().map_err(|_| format!("Invalid position '{}'", val.trim()))?);
     }
 
-    let mut min_pos_fuel: Option<[usize; 2]> = None;
+    Ok(crabs)
+}
 
-    for i in min..=max {
-        let fuel = align_crabs_to(&crabs, i);
+pub fn run(contents: &str) -> Result<(), String> {
+    print!("[Crabs]...       ");
 
-        min_pos_fuel = match min_pos_fuel {
-            None => Some([i, fuel]),
-            Some(p) => {
-                if fuel < p[1] {
-                    Some([i, fuel])
-                } else {
-                    Some(p)
-                }
-            }
-        };
-    }
+    let crabs = load_crabs(contents)?;
 
-    if let Some(p) = min_pos_fuel {
-        println!("{}", p[1]);
+    match (align_crabs(&crabs, &ConstantCost), align_crabs(&crabs, &TriangularCost)) {
+        (Some([_, constant]), Some([_, triangular])) => println!("{} {}", constant, triangular),
+        _ => return Err(String::from("No crabs found")),
     }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,16 +1,242 @@
 use std::str::FromStr;
+use crate::solver::{self, Implementation, Kind, Mode};
+
+// Segment sets are bitmasks, wire 'a' being bit 0, wire 'b' bit 1, etc.
+type Segments = u32;
+
+/// Segment index driven by each wire.
+pub type Wiring = Vec<usize>;
+
+const SEVEN_SEGMENT: &str = "\
+0 abcefg
+1 cf
+2 acdeg
+3 acdfg
+4 bcdf
+5 abdfg
+6 abdefg
+7 acf
+8 abcdefg
+9 abcdfg";
+
+const IMPLEMENTATIONS: [Implementation<(), usize>; 2] = [
+    Implementation { name: "deduction", kind: Kind::Fast, solve: sum_by_deduction },
+    Implementation { name: "constraint solver", kind: Kind::Reference, solve: sum_by_constraints },
+];
+
+fn to_segments(pattern: &str) -> Result<Segments, String> {
+    let mut mask = 0;
+
+    for c in pattern.chars() {
+        if !c.is_ascii_lowercase() {
+            return Err(format!("Invalid wire '{}' in pattern '{}'", c, pattern));
+        }
+
+        let bit = 1 << (c as u8 - b'a');
+        if mask & bit != 0 {
+            return Err(format!("Wire '{}' appears twice in pattern '{}'", c, pattern));
+        }
+        mask |= bit;
+    }
+
+    Ok(mask)
+}
+
+/// The glyphs a display can show, as the segments lit for each of them.
+pub struct GlyphTable {
+    glyphs: Vec<(char, Segments)>,
+    segments: usize,
+}
+
+impl GlyphTable {
+    pub fn seven_segment() -> GlyphTable {
+        GlyphTable::from_str(SEVEN_SEGMENT).unwrap()
+    }
+
+    fn all_segments(&self) -> Segments {
+        ((1u64 << self.segments) - 1) as Segments
+    }
+
+    fn glyph(&self, segments: Segments) -> Option<char> {
+        self.glyphs.iter().find(|(_, g)| *g == segments).map(|(c, _)| *c)
+    }
+
+    fn rewire(wiring: &[usize], pattern: Segments) -> Segments {
+        let mut segments = 0;
+        for (wire, segment) in wiring.iter().enumerate() {
+            if pattern & (1 << wire) != 0 {
+                segments |= 1 << segment;
+            }
+        }
+        segments
+    }
+
+    pub fn decode(&self, wiring: &[usize], pattern: Segments) -> Option<char> {
+        self.glyph(GlyphTable::rewire(wiring, pattern))
+    }
+
+    // Narrow down the segments each wire can drive until nothing changes.
+    // Return false if a wire is left without any possible segment.
+    fn propagate(&self, patterns: &[Segments], domains: &mut [Segments]) -> bool {
+        let all = self.all_segments();
+
+        loop {
+            let before = domains.to_vec();
+
+            // A pattern shows one of the glyphs with the same number of segments that is still
+            // reachable: wires in the pattern drive one of its segments, the others do not.
+            for p in patterns {
+                let mut lit = 0;
+                let mut unlit = 0;
+
+                for (_, g) in &self.glyphs {
+                    let reachable = g.count_ones() == p.count_ones() &&
+                        domains.iter().enumerate().all(|(w, d)| match p & (1 << w) != 0 {
+                            true => d & g != 0,
+                            false => d & !g & all != 0,
+                        });
+
+                    if reachable {
+                        lit |= g;
+                        unlit |= !g & all;
+                    }
+                }
+
+                for (w, d) in domains.iter_mut().enumerate() {
+                    *d &= if p & (1 << w) != 0 { lit } else { unlit };
+                    if *d == 0 {
+                        return false;
+                    }
+                }
+            }
+
+            // Two wires cannot drive the same segment
+            for w in 0..domains.len() {
+                if domains[w].count_ones() != 1 {
+                    continue;
+                }
+                for o in 0..domains.len() {
+                    if o != w {
+                        domains[o] &= !domains[w];
+                        if domains[o] == 0 {
+                            return false;
+                        }
+                    }
+                }
+            }
+
+            if domains == before.as_slice() {
+                return true;
+            }
+        }
+    }
+
+    fn search(&self, patterns: &[Segments], mut domains: Vec<Segments>, wirings: &mut Vec<Wiring>) {
+        if !self.propagate(patterns, &mut domains) {
+            return;
+        }
+
+        // Branch on the wire with the fewest options left
+        let undecided = (0..domains.len())
+            .filter(|w| domains[*w].count_ones() > 1)
+            .min_by_key(|w| domains[*w].count_ones());
+
+        match undecided {
+            None => {
+                let wiring: Wiring = domains.iter().map(|d| d.trailing_zeros() as usize).collect();
+                if patterns.iter().all(|p| self.decode(&wiring, *p).is_some()) {
+                    wirings.push(wiring);
+                }
+            },
+            Some(w) => {
+                for s in 0..self.segments {
+                    if domains[w] & (1 << s) != 0 {
+                        let mut d = domains.clone();
+                        d[w] = 1 << s;
+                        self.search(patterns, d, wirings);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every wiring under which all the patterns show a glyph of the table.
+    pub fn solve(&self, patterns: &[Segments]) -> Result<Vec<Wiring>, String> {
+        if let Some(p) = patterns.iter().find(|p| **p & !self.all_segments() != 0) {
+            return Err(format!("Pattern {:b} uses more wires than the {} segments of the display", p, self.segments));
+        }
+
+        let mut wirings = vec![];
+        self.search(patterns, vec![self.all_segments(); self.segments], &mut wirings);
+
+        if wirings.is_empty() {
+            return Err(String::from("Inconsistent observation: no wiring matches the patterns"));
+        }
+
+        Ok(wirings)
+    }
+}
+
+/// One glyph per line: the character it shows followed by its segments, e.g. "1 cf".
+impl FromStr for GlyphTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut glyphs: Vec<(char, Segments)> = vec![];
+        let mut all = 0;
+
+        for line in s.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let vals: Vec<&str> = line.split_whitespace().collect();
+            let c = match vals[..] {
+                [c, _] if c.chars().count() == 1 => c.chars().next().unwrap(),
+                _ => return Err(format!("Invalid glyph '{}'", line)),
+            };
+            let segments = to_segments(vals[1])?;
+
+            if glyphs.iter().any(|(_, g)| *g == segments) {
+                return Err(format!("Glyph '{}' has the same segments as another one", c));
+            }
+
+            all |= segments;
+            glyphs.push((c, segments));
+        }
+
+        Ok(GlyphTable {
+            glyphs,
+            segments: (32 - all.leading_zeros()) as usize,
+        })
+    }
+}
+
+fn contains(set: Segments, subset: Segments) -> bool {
+    set & subset == subset
+}
+
+// Return the only pattern matching the predicate, or an error if there are none or several
+fn only(patterns: &[Segments], digit: usize, predicate: impl Fn(Segments) -> bool) -> Result<Segments, String> {
+    let candidates: Vec<Segments> = patterns.iter().copied().filter(|p| predicate(*p)).collect();
+
+    match candidates.len() {
+        1 => Ok(candidates[0]),
+        0 => Err(format!("Inconsistent observation: no pattern for digit {}", digit)),
+        n => Err(format!("Ambiguous observation: {} patterns for digit {}", n, digit)),
+    }
+}
 
-#[allow(dead_code)]
 struct Observation {
-    patterns: Vec<String>,
-    outputs: Vec<String>,
+    patterns: Vec<Segments>,
+    outputs: Vec<Segments>,
 }
 
 impl Observation {
     pub fn base_numbers(&self) -> usize {
         let mut count = 0;
         for v in &self.outputs {
-            count += match v.len() {
+            count += match v.count_ones() {
                 2 | 3 | 4 | 7 => 1,
                 _ => 0,
             }
@@ -18,6 +244,73 @@ impl Observation {
 
         count
     }
+
+    /// Find the pattern used for each digit.
+    pub fn wiring(&self) -> Result<[Segments; 10], String> {
+        let mut digits = [0; 10];
+        let p = &self.patterns;
+
+        if p.len() != 10 {
+            return Err(format!("Expected 10 patterns, got {}", p.len()));
+        }
+
+        // 1, 4, 7 and 8 are the only ones with their number of segments
+        digits[1] = only(p, 1, |x| x.count_ones() == 2)?;
+        digits[4] = only(p, 4, |x| x.count_ones() == 4)?;
+        digits[7] = only(p, 7, |x| x.count_ones() == 3)?;
+        digits[8] = only(p, 8, |x| x.count_ones() == 7)?;
+
+        // 0, 6 and 9 have 6 segments: only 9 covers 4, and only 0 covers 1 without covering 4
+        digits[9] = only(p, 9, |x| x.count_ones() == 6 && contains(x, digits[4]))?;
+        digits[0] = only(p, 0, |x| x.count_ones() == 6 && !contains(x, digits[4]) && contains(x, digits[1]))?;
+        digits[6] = only(p, 6, |x| x.count_ones() == 6 && !contains(x, digits[1]))?;
+
+        // 2, 3 and 5 have 5 segments: only 3 covers 1, and only 5 is covered by 6
+        digits[3] = only(p, 3, |x| x.count_ones() == 5 && contains(x, digits[1]))?;
+        digits[5] = only(p, 5, |x| x.count_ones() == 5 && contains(digits[6], x))?;
+        digits[2] = only(p, 2, |x| x.count_ones() == 5 && x != digits[3] && x != digits[5])?;
+
+        Ok(digits)
+    }
+
+    pub fn decode(&self) -> Result<usize, String> {
+        let digits = self.wiring()?;
+        let mut value = 0;
+
+        for o in &self.outputs {
+            match digits.iter().position(|d| d == o) {
+                Some(d) => value = value * 10 + d,
+                None => return Err(format!("Output {:b} does not match any pattern", o)),
+            }
+        }
+
+        Ok(value)
+    }
+
+    pub fn wirings(&self, table: &GlyphTable) -> Result<Vec<Wiring>, String> {
+        table.solve(&self.patterns)
+    }
+
+    /// Decode the outputs with any glyph table. Several wirings may fit the patterns, this only
+    /// fails if they do not agree on the outputs.
+    pub fn decode_with(&self, table: &GlyphTable) -> Result<String, String> {
+        let mut decoded: Vec<String> = vec![];
+
+        for wiring in self.wirings(table)? {
+            let text = self.outputs.iter()
+                .map(|o| table.decode(&wiring, *o).ok_or(format!("Output {:b} does not match any glyph", o)))
+                .collect::<Result<String, String>>()?;
+
+            if !decoded.contains(&text) {
+                decoded.push(text);
+            }
+        }
+
+        match decoded.len() {
+            1 => Ok(decoded.remove(0)),
+            _ => Err(format!("Ambiguous observation: the outputs can read as {}", decoded.join(", "))),
+        }
+    }
 }
 
 impl FromStr for Observation {
@@ -25,18 +318,51 @@ impl FromStr for Observation {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let obs: Vec<&str> = s.split('|').collect();
-        let patterns: Vec<String> = obs[0].split(' ').map(|x| x.to_string()).collect();
-        let outputs: Vec<String> = obs[1].split(' ').map(|x| x.to_string()).collect();
+        if obs.len() != 2 {
+            return Err(format!("Invalid observation '{}'", s));
+        }
+
+        let patterns = obs[0].split_whitespace().map(to_segments).collect::<Result<Vec<_>, _>>()?;
+        let outputs = obs[1].split_whitespace().map(to_segments).collect::<Result<Vec<_>, _>>()?;
 
         Ok(Observation {
             patterns,
             outputs
-
         })
     }
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
+fn sum_by_deduction(contents: &str, _: &()) -> Result<usize, String> {
+    let mut sum = 0;
+
+    for val in contents.lines() {
+        if val.is_empty() {
+            continue;
+        }
+
+        sum += Observation::from_str(val)?.decode()?;
+    }
+
+    Ok(sum)
+}
+
+fn sum_by_constraints(contents: &str, _: &()) -> Result<usize, String> {
+    let table = GlyphTable::seven_segment();
+    let mut sum = 0;
+
+    for val in contents.lines() {
+        if val.is_empty() {
+            continue;
+        }
+
+        let text = Observation::from_str(val)?.decode_with(&table)?;
+        sum += text.parse::<usize>().map_err(|e| e.to_string())?;
+    }
+
+    Ok(sum)
+}
+
+pub fn run(contents: &str, mode: Mode) -> Result<(), String> {
     print!("[Display]...     ");
 
     let mut count = 0;
@@ -47,9 +373,11 @@ pub fn run(contents: &str) -> Result<(), String> {
             continue;
         }
 
-        count += Observation::from_str(val).unwrap().base_numbers();
+        count += Observation::from_str(val)?.base_numbers();
     }
 
-    println!("{}, ?", count);
+    let sum = solver::solve(&IMPLEMENTATIONS, mode, contents, &())?;
+
+    println!("{} {}", count, sum);
     Ok(())
-}
\ No newline at end of file
+}
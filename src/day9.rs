@@ -1,6 +1,7 @@
-use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
 
-fn is_min(map: &Vec<Vec<u8>>, pos: [usize; 2]) -> bool {
+fn is_min(map: &[Vec<u8>], pos: [usize; 2]) -> bool {
     let i = pos[0];
     let j = pos[1];
     let val = map[i][j];
@@ -10,69 +11,140 @@ fn is_min(map: &Vec<Vec<u8>>, pos: [usize; 2]) -> bool {
         (j == map[i].len()-1 || map[i][j+1] > val)
 }
 
-fn find_connected(map: &mut Vec<Vec<u8>>, pos: [usize; 2], entries: &mut BTreeSet<[usize; 2]>) {
-    let i = pos[0];
-    let j = pos[1];
+const WALL: u8 = 9;
 
-    entries.insert(pos);
-    if i > 0 && map[i-1][j] < 9 && !entries.contains(&[i-1, j]) {
-        find_connected(map, [i-1, j], entries);
-    }
+/// Basin id of every cell, None for the walls of height 9.
+pub struct BasinMap {
+    labels: Vec<Vec<Option<usize>>>,
+    sizes: Vec<usize>,
+}
 
-    if j > 0 && map[i][j-1] < 9 && !entries.contains(&[i, j-1]) {
-        find_connected(map, [i, j-1], entries);
-    }
+impl BasinMap {
+    /// Label every non-9 cell with the id of its basin, in a single sweep that flood fills each
+    /// new basin from the first cell found in it. Basins are found whatever their bottom looks
+    /// like, including flat plateaus that have no strict low point.
+    pub fn from_heights(map: &[Vec<u8>]) -> BasinMap {
+        let mut labels: Vec<Vec<Option<usize>>> = map.iter().map(|row| vec![None; row.len()]).collect();
+        let mut sizes = vec![];
+        let mut stack = vec![];
+
+        for i in 0..map.len() {
+            for j in 0..map[i].len() {
+                if map[i][j] == WALL || labels[i][j].is_some() {
+                    continue;
+                }
+
+                let id = sizes.len();
+                let mut size = 0;
+
+                labels[i][j] = Some(id);
+                stack.push([i, j]);
+
+                while let Some([x, y]) = stack.pop() {
+                    size += 1;
+
+                    for [nx, ny] in neighbours(map, [x, y]) {
+                        if map[nx][ny] != WALL && labels[nx][ny].is_none() {
+                            labels[nx][ny] = Some(id);
+                            stack.push([nx, ny]);
+                        }
+                    }
+                }
+
+                sizes.push(size);
+            }
+        }
 
-    if i < (map.len()-1) && map[i+1][j] < 9 && !entries.contains(&[i+1, j]) {
-        find_connected(map, [i+1, j], entries);
+        BasinMap { labels, sizes }
     }
 
-    if j < (map[i].len()-1) && map[i][j+1] < 9 && !entries.contains(&[i, j+1]) {
-        find_connected(map, [i, j+1], entries);
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
     }
-}
 
-fn build_basin(map: &mut Vec<Vec<u8>>, pos: [usize; 2]) -> usize {
-    let mut entries = BTreeSet::new();
+    /// Basin id of every cell separated by spaces, -1 for walls.
+    pub fn render_ids(&self) -> String {
+        let mut text = String::new();
 
-    find_connected(map, pos, &mut entries);
+        for row in &self.labels {
+            let ids: Vec<String> = row.iter()
+                .map(|cell| cell.map_or(String::from("-1"), |id| id.to_string()))
+                .collect();
+            text.push_str(&ids.join(" "));
+            text.push('\n');
+        }
 
-    entries.len()
-}
+        text
+    }
 
-fn make_basins(map: &mut Vec<Vec<u8>>) -> Vec<usize> {
-    let mut basins = vec![];
-    for i in 0..map.len() {
-        for j in 0..map[i].len() {
-            if is_min(map, [i,j]) {
-                basins.push(build_basin(map, [i,j]));
-                //println!("{}", build_basin(map, [i,j]));
+    /// One character per cell: '#' for walls, a letter or digit identifying the basin otherwise.
+    /// With more than 62 basins, the characters are reused, so this is only an overview.
+    pub fn render(&self) -> String {
+        const NAMES: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let mut text = String::new();
+
+        for row in &self.labels {
+            for cell in row {
+                text.push(match cell {
+                    None => '#',
+                    Some(id) => NAMES[id % NAMES.len()] as char,
+                });
             }
+            text.push('\n');
         }
-    }
 
-    basins
+        text
+    }
 }
 
-pub fn run(contents: &str) -> Result<(), String> {
-    print!("[Tubes]...       ");
+fn neighbours(map: &[Vec<u8>], pos: [usize; 2]) -> Vec<[usize; 2]> {
+    let [i, j] = pos;
+    let mut ret = vec![];
 
+    if i > 0 {
+        ret.push([i-1, j]);
+    }
+    if j > 0 {
+        ret.push([i, j-1]);
+    }
+    if i < map.len()-1 {
+        ret.push([i+1, j]);
+    }
+    if j < map[i].len()-1 {
+        ret.push([i, j+1]);
+    }
+
+    ret
+}
+
+fn load_map(contents: &str) -> Result<Vec<Vec<u8>>, String> {
     let mut map: Vec<Vec<u8>> = vec![];
 
-    // Load input data
     for val in contents.lines() {
         if val.is_empty() {
             continue;
         }
 
-        map.push(Vec::from_iter(
-            val.chars().map(|x| {
-                let t: u8 = String::from(x).parse().unwrap();
-                t
-            })
-        ));
+        map.push(val.chars()
+            .map(|x| x.to_digit(10).map(|d| d as u8).ok_or(format!("Invalid height '{}'", x)))
+            .collect::<Result<Vec<u8>, String>>()?);
     }
 
+    Ok(map)
+}
+
+pub fn export(contents: &str, dir: &Path) -> Result<(), String> {
+    let basins = BasinMap::from_heights(&load_map(contents)?);
+
+    fs::write(dir.join("2021-09.txt"), basins.render_ids()).map_err(|e| e.to_string())?;
+    fs::write(dir.join("2021-09-letters.txt"), basins.render()).map_err(|e| e.to_string())
+}
+
+pub fn run(contents: &str) -> Result<(), String> {
+    print!("[Tubes]...       ");
+
+    let map = load_map(contents)?;
+
     let mut lows: usize = 0;
     for i in 0..map.len() {
         for j in 0..map[i].len() {
@@ -82,7 +154,11 @@ pub fn run(contents: &str) -> Result<(), String> {
         }
     }
 
-    let mut basins = make_basins(&mut map);
+    let mut basins = BasinMap::from_heights(&map).sizes().to_vec();
+
+    if basins.len() < 3 {
+        return Err(format!("Only {} basins found", basins.len()));
+    }
 
     basins.sort();
     basins.reverse();
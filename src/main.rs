@@ -1,3 +1,4 @@
+mod bigint;
 mod day1;
 mod day2;
 mod day3;
@@ -21,8 +22,43 @@ mod day20;
 mod day21;
 mod day22;
 mod day23;
+mod matrix;
+mod solver;
+
+use std::env;
+use std::path::PathBuf;
+
+/// Directory given with `--export <dir>`. Days that can render their data write files there.
+fn export_dir() -> Option<PathBuf> {
+    let args: Vec<String> = env::args().collect();
+
+    args.iter()
+        .position(|a| a == "--export")
+        .and_then(|i| args.get(i + 1))
+        .map(PathBuf::from)
+}
+
+/// `--check` runs every registered implementation of a day and fails if they disagree.
+fn solver_mode() -> solver::Mode {
+    match env::args().any(|a| a == "--check") {
+        true => solver::Mode::Check,
+        false => solver::Mode::Normal,
+    }
+}
+
+/// `--strict` makes day 16 fail on values over 64 bits instead of warning.
+fn overflow_mode() -> day16::Overflow {
+    match env::args().any(|a| a == "--strict") {
+        true => day16::Overflow::Error,
+        false => day16::Overflow::Warn,
+    }
+}
 
 fn main() -> Result<(), String> {
+    let export = export_dir();
+    let mode = solver_mode();
+    let overflow = overflow_mode();
+
     match day1::run(include_str!("../inputs/2021-01.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
@@ -48,22 +84,31 @@ fn main() -> Result<(), String> {
         Err(e) => return Err(e.to_string())
     }
 
-    match day6::run(include_str!("../inputs/2021-06.txt")) {
-        Ok(_) => {},
-        Err(e) => return Err(e.to_string())
+    if let Some(dir) = &export {
+        match day5::export(include_str!("../inputs/2021-05.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
     }
 
-    match day6::run2(include_str!("../inputs/2021-06.txt")) {
+    match day6::run(include_str!("../inputs/2021-06.txt"), mode) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
+    if let Some(dir) = &export {
+        match day6::export(include_str!("../inputs/2021-06.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
     match day7::run(include_str!("../inputs/2021-07.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
-    match day8::run(include_str!("../inputs/2021-08.txt")) {
+    match day8::run(include_str!("../inputs/2021-08.txt"), mode) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
@@ -73,46 +118,109 @@ fn main() -> Result<(), String> {
         Err(e) => return Err(e.to_string())
     }
 
+    if let Some(dir) = &export {
+        match day9::export(include_str!("../inputs/2021-09.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
     match day10::run(include_str!("../inputs/2021-10.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
+    if let Some(dir) = &export {
+        match day10::export(include_str!("../inputs/2021-10.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
     match day11::run(include_str!("../inputs/2021-11.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
-    match day12::run(include_str!("../inputs/2021-12.txt")) {
+    if let Some(dir) = &export {
+        match day11::export(include_str!("../inputs/2021-11.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
+    match day12::run(include_str!("../inputs/2021-12.txt"), mode) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
+    if let Some(dir) = &export {
+        match day12::export(include_str!("../inputs/2021-12.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
     match day13::run(include_str!("../inputs/2021-13.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
-    match day14::run(include_str!("../inputs/2021-14.txt")) {
+    if let Some(dir) = &export {
+        match day13::export(include_str!("../inputs/2021-13.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
+    match day14::run(include_str!("../inputs/2021-14.txt"), mode) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
+    if let Some(dir) = &export {
+        match day14::export(include_str!("../inputs/2021-14.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
     match day15::run(include_str!("../inputs/2021-15.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
-    match day16::run(include_str!("../inputs/2021-16.txt")) {
+    if let Some(dir) = &export {
+        match day15::export(include_str!("../inputs/2021-15.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
+    match day16::run(include_str!("../inputs/2021-16.txt"), mode, overflow) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
-    match day17::run() {
+    if let Some(dir) = &export {
+        match day16::export(include_str!("../inputs/2021-16.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
+    match day17::run(include_str!("../inputs/2021-17.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
     }
 
+    if let Some(dir) = &export {
+        match day17::export(include_str!("../inputs/2021-17.txt"), dir) {
+            Ok(_) => {},
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
     match day18::run(include_str!("../inputs/2021-18.txt")) {
         Ok(_) => {},
         Err(e) => return Err(e.to_string())
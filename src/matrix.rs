@@ -0,0 +1,98 @@
+use crate::bigint::BigUint;
+
+/// Square matrix, as a list of rows.
+pub type Matrix<T> = Vec<Vec<T>>;
+
+fn mul_mod(a: &[Vec<u64>], b: &[Vec<u64>], modulus: u64) -> Matrix<u64> {
+    let n = a.len();
+    let mut ret = vec![vec![0; n]; n];
+
+    for i in 0..n {
+        for k in 0..n {
+            if a[i][k] == 0 {
+                continue;
+            }
+            for j in 0..n {
+                let prod = (a[i][k] as u128 * b[k][j] as u128) % modulus as u128;
+                ret[i][j] = ((ret[i][j] as u128 + prod) % modulus as u128) as u64;
+            }
+        }
+    }
+
+    ret
+}
+
+fn mul_big(a: &[Vec<BigUint>], b: &[Vec<BigUint>]) -> Matrix<BigUint> {
+    let n = a.len();
+    let mut ret = vec![vec![BigUint::zero(); n]; n];
+
+    for i in 0..n {
+        for k in 0..n {
+            if a[i][k].is_zero() {
+                continue;
+            }
+            for j in 0..n {
+                ret[i][j] += &(&a[i][k] * &b[k][j]);
+            }
+        }
+    }
+
+    ret
+}
+
+/// Generic exponentiation by squaring.
+fn pow<T: Clone>(m: &[Vec<T>], mut exp: u64, identity: Matrix<T>,
+                 mul: impl Fn(&[Vec<T>], &[Vec<T>]) -> Matrix<T>) -> Matrix<T> {
+    let mut result = identity;
+    let mut base = m.to_vec();
+
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul(&result, &base);
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = mul(&base, &base);
+        }
+    }
+
+    result
+}
+
+/// m^exp with every value modulo `modulus`, in O(log exp) products.
+pub fn pow_mod(m: &[Vec<u64>], exp: u64, modulus: u64) -> Matrix<u64> {
+    let n = m.len();
+    let reduced: Matrix<u64> = m.iter().map(|row| row.iter().map(|v| v % modulus).collect()).collect();
+    let identity = (0..n).map(|i| (0..n).map(|j| (i == j) as u64 % modulus).collect()).collect();
+
+    pow(&reduced, exp, identity, |a, b| mul_mod(a, b, modulus))
+}
+
+/// Exact m^exp, in O(log exp) products.
+pub fn pow_big(m: &[Vec<u64>], exp: u64) -> Matrix<BigUint> {
+    let n = m.len();
+    let big: Matrix<BigUint> = m.iter().map(|row| row.iter().map(|v| BigUint::from(*v)).collect()).collect();
+    let identity = (0..n).map(|i| (0..n).map(|j| BigUint::from((i == j) as u64)).collect()).collect();
+
+    pow(&big, exp, identity, mul_big)
+}
+
+/// m * v with every value modulo `modulus`.
+pub fn apply_mod(m: &[Vec<u64>], v: &[u64], modulus: u64) -> Vec<u64> {
+    m.iter()
+        .map(|row| row.iter().zip(v).fold(0u64, |acc, (a, b)| {
+            let prod = (*a as u128 * (*b % modulus) as u128) % modulus as u128;
+            ((acc as u128 + prod) % modulus as u128) as u64
+        }))
+        .collect()
+}
+
+/// Exact m * v.
+pub fn apply_big(m: &[Vec<BigUint>], v: &[u64]) -> Vec<BigUint> {
+    m.iter()
+        .map(|row| row.iter().zip(v).fold(BigUint::zero(), |mut acc, (a, b)| {
+            acc += &(a * &BigUint::from(*b));
+            acc
+        }))
+        .collect()
+}
@@ -0,0 +1,49 @@
+use std::fmt::Display;
+
+/// Role of an implementation when a day ships more than one.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// Straightforward but slow, only run to cross-check the others.
+    Reference,
+    /// Used to produce the answer.
+    Fast,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Only the first fast implementation runs.
+    Normal,
+    /// Every implementation runs on the same input and parameters and they must agree.
+    Check,
+}
+
+/// One way of solving a day, `P` being the parameters shared by all implementations of that day.
+pub struct Implementation<P, R> {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub solve: fn(&str, &P) -> Result<R, String>,
+}
+
+pub fn solve<P, R>(implementations: &[Implementation<P, R>], mode: Mode, contents: &str, params: &P)
+    -> Result<R, String>
+    where R: PartialEq + Display {
+    let fast = match implementations.iter().find(|i| i.kind == Kind::Fast) {
+        Some(i) => i,
+        None => return Err(String::from("No fast implementation registered")),
+    };
+
+    let answer = (fast.solve)(contents, params)?;
+
+    if mode == Mode::Check {
+        for other in implementations.iter().filter(|i| i.name != fast.name) {
+            let other_answer = (other.solve)(contents, params)?;
+            if other_answer != answer {
+                return Err(format!("{} ({:?}) found {} but {} ({:?}) found {}",
+                                   other.name, other.kind, other_answer,
+                                   fast.name, fast.kind, answer));
+            }
+        }
+    }
+
+    Ok(answer)
+}